@@ -6,18 +6,44 @@ in vec2 TexCoord;
 uniform sampler2D terminalTexture;
 uniform float time;
 uniform vec2 resolution;
+uniform float focused;
+uniform vec4 cursor_rect;  // x, y, width, height in texture coordinates, zero size when hidden
+uniform float bell_time;   // time of the last bell, in the same clock as `time`
+uniform float bell_mode;   // 0 = off, 1 = flash, 2 = glitch burst
 
 // Terminal effect parameters
 const float SCANLINE_INTENSITY = 0.05;
 const float GLOW_STRENGTH = 0.25;
 const vec3 GLOW_COLOR = vec3(0.0, 1.0, 0.7);  // Cyberpunk green
 const float CHROMATIC_ABERRATION = 0.002;
+const float UNFOCUSED_DIM = 0.6;
+const float CURSOR_GLOW_STRENGTH = 0.15;
+const float CURSOR_GLOW_FALLOFF = 60.0;
+const float BELL_DURATION = 0.15;
+const float BELL_FLASH_STRENGTH = 0.35;
+const float BELL_GLITCH_SHIFT = 0.02;
+
+float hash(float n) {
+    return fract(sin(n) * 43758.5453);
+}
 
 void main() {
+    // Bell intensity, fading from 1 to 0 over the bell duration
+    float bell = clamp(1.0 - (time - bell_time) / BELL_DURATION, 0.0, 1.0);
+
+    // Glitch burst: shift horizontal bands sideways and widen the color split
+    vec2 uv = TexCoord;
+    float aberration = CHROMATIC_ABERRATION;
+    if (bell_mode > 1.5 && bell > 0.0) {
+        float band = floor(uv.y * 24.0);
+        uv.x += (hash(band + floor(time * 60.0)) - 0.5) * BELL_GLITCH_SHIFT * bell;
+        aberration += BELL_GLITCH_SHIFT * 0.5 * bell;
+    }
+
     // Basic texture sampling with chromatic aberration
-    vec4 baseColor = texture(terminalTexture, TexCoord);
-    vec4 rColor = texture(terminalTexture, TexCoord + vec2(CHROMATIC_ABERRATION, 0.0));
-    vec4 bColor = texture(terminalTexture, TexCoord - vec2(CHROMATIC_ABERRATION, 0.0));
+    vec4 baseColor = texture(terminalTexture, uv);
+    vec4 rColor = texture(terminalTexture, uv + vec2(aberration, 0.0));
+    vec4 bColor = texture(terminalTexture, uv - vec2(aberration, 0.0));
     
     vec4 color = baseColor;
     color.r = rColor.r;
@@ -32,9 +58,24 @@ void main() {
     vec3 glow = GLOW_COLOR * luminance * GLOW_STRENGTH;
     color.rgb += glow;
 
+    // Soft glow around the cursor
+    if (cursor_rect.z > 0.0) {
+        vec2 nearest = clamp(TexCoord, cursor_rect.xy, cursor_rect.xy + cursor_rect.zw);
+        float dist = length((TexCoord - nearest) * resolution / resolution.y);
+        color.rgb += GLOW_COLOR * exp(-dist * CURSOR_GLOW_FALLOFF) * CURSOR_GLOW_STRENGTH;
+    }
+
     // Screen flicker
     float flicker = sin(time * 10.0) * 0.02 + 0.98;
     color.rgb *= flicker;
 
+    // Visual bell flash
+    if (bell_mode > 0.5 && bell_mode < 1.5) {
+        color.rgb = mix(color.rgb, vec3(1.0), bell * BELL_FLASH_STRENGTH);
+    }
+
+    // Dim the whole frame while the window is unfocused
+    color.rgb *= mix(UNFOCUSED_DIM, 1.0, focused);
+
     FragColor = color;
 }
@@ -0,0 +1,80 @@
+// Audible bell: a custom WAV file or a short generated beep, played through an SDL audio queue
+
+use sdl2::audio::{AudioCVT, AudioFormat, AudioQueue, AudioSpecDesired, AudioSpecWAV};
+use sdl2::AudioSubsystem;
+use std::path::Path;
+
+const BEEP_FREQUENCY: f32 = 880.0;
+const BEEP_DURATION: f32 = 0.12;
+const BEEP_VOLUME: f32 = 0.2;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualBell {
+    Off,
+    Flash,
+    Glitch,
+}
+
+pub struct AudibleBell {
+    queue: AudioQueue<i16>,
+    samples: Vec<i16>,
+}
+
+impl VisualBell {
+    // Effect selector passed to the fragment shader
+    pub fn shader_mode(self) -> f32 {
+        match self {
+            VisualBell::Off => 0.0,
+            VisualBell::Flash => 1.0,
+            VisualBell::Glitch => 2.0,
+        }
+    }
+}
+
+impl AudibleBell {
+    pub fn new(audio: &AudioSubsystem, sound: Option<&Path>) -> Result<Self, String> {
+        let desired = AudioSpecDesired { freq: Some(44_100), channels: Some(1), samples: None };
+        let queue = audio.open_queue::<i16, _>(None, &desired)?;
+        let (rate, channels) = (queue.spec().freq, queue.spec().channels);
+
+        // Fall back to the beep when the custom sound can't be used
+        let samples = match sound {
+            Some(path) => load_wav(path, rate, channels).unwrap_or_else(|e| {
+                eprintln!("Bell: failed to load {}: {}", path.display(), e);
+                beep(rate, channels)
+            }),
+            None => beep(rate, channels),
+        };
+        Ok(Self { queue, samples })
+    }
+
+    // Restarts the sound rather than queueing bells back to back
+    pub fn ring(&self) {
+        self.queue.clear();
+        if let Err(e) = self.queue.queue_audio(&self.samples) {
+            eprintln!("Bell: {}", e);
+        }
+        self.queue.resume();
+    }
+}
+
+fn load_wav(path: &Path, rate: i32, channels: u8) -> Result<Vec<i16>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::s16_sys(), channels, rate)?;
+    let data = cvt.convert(wav.buffer().to_vec());
+    Ok(data.chunks_exact(2).map(|pair| i16::from_ne_bytes([pair[0], pair[1]])).collect())
+}
+
+// A sine tone with a linear fade out, so it doesn't click when it stops
+fn beep(rate: i32, channels: u8) -> Vec<i16> {
+    let frames = (rate as f32 * BEEP_DURATION) as usize;
+    (0..frames)
+        .flat_map(|i| {
+            let t = i as f32 / rate as f32;
+            let fade = 1.0 - i as f32 / frames as f32;
+            let sample = (t * BEEP_FREQUENCY * std::f32::consts::TAU).sin() * fade * BEEP_VOLUME;
+            std::iter::repeat_n((sample * i16::MAX as f32) as i16, channels as usize)
+        })
+        .collect()
+}
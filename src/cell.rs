@@ -0,0 +1,196 @@
+// Styled cell storage for terminal lines
+
+use super::hyperlink::HyperlinkId;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CellColor {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// Graphic rendition set by SGR
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    pub fg: CellColor,
+    pub bg: CellColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cell {
+    pub c: char,
+    pub style: Style,
+    pub hyperlink: Option<HyperlinkId>,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { c: ' ', style: Style::default(), hyperlink: None }
+    }
+}
+
+impl Style {
+    // Applies one SGR sequence, consuming extended color sub-parameters as needed
+    pub fn apply_sgr(&mut self, params: &vte::Params) {
+        let mut iter = params.iter();
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+
+        while let Some(param) = iter.next() {
+            match param {
+                [0] => *self = Style::default(),
+                [1] => self.bold = true,
+                [2] => self.dim = true,
+                [3] => self.italic = true,
+                [4, 0] => self.underline = false,
+                [4] | [4, ..] => self.underline = true,
+                [7] => self.inverse = true,
+                [8] => self.hidden = true,
+                [9] => self.strikethrough = true,
+                [21] => self.underline = true,
+                [22] => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                [23] => self.italic = false,
+                [24] => self.underline = false,
+                [27] => self.inverse = false,
+                [28] => self.hidden = false,
+                [29] => self.strikethrough = false,
+                [n @ 30..=37] => self.fg = CellColor::Indexed((n - 30) as u8),
+                [38, rest @ ..] => {
+                    if let Some(color) = extended_color(rest, &mut iter) {
+                        self.fg = color;
+                    }
+                }
+                [39] => self.fg = CellColor::Default,
+                [n @ 40..=47] => self.bg = CellColor::Indexed((n - 40) as u8),
+                [48, rest @ ..] => {
+                    if let Some(color) = extended_color(rest, &mut iter) {
+                        self.bg = color;
+                    }
+                }
+                [49] => self.bg = CellColor::Default,
+                [n @ 90..=97] => self.fg = CellColor::Indexed((n - 90 + 8) as u8),
+                [n @ 100..=107] => self.bg = CellColor::Indexed((n - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl CellColor {
+    // SGR parameters selecting this color as foreground (base 30) or background (base 40)
+    fn sgr_params(self, base: u16) -> Option<String> {
+        match self {
+            CellColor::Default => None,
+            CellColor::Indexed(index) if index < 8 => Some((base + index as u16).to_string()),
+            CellColor::Indexed(index) if index < 16 => Some((base + 60 + index as u16 - 8).to_string()),
+            CellColor::Indexed(index) => Some(format!("{}:5:{}", base + 8, index)),
+            CellColor::Rgb(r, g, b) => Some(format!("{}:2::{}:{}:{}", base + 8, r, g, b)),
+        }
+    }
+}
+
+impl Style {
+    // SGR parameters that reproduce this style from a reset state, e.g. "0;1;38:5:208"
+    pub fn to_sgr(self) -> String {
+        let mut params = vec!["0".to_string()];
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.inverse, "7"),
+            (self.hidden, "8"),
+            (self.strikethrough, "9"),
+        ];
+        params.extend(flags.iter().filter(|(set, _)| *set).map(|(_, param)| param.to_string()));
+        params.extend(self.fg.sgr_params(30));
+        params.extend(self.bg.sgr_params(40));
+        params.join(";")
+    }
+}
+
+// Handles both "38;5;n" / "38;2;r;g;b" and the colon forms "38:5:n" / "38:2::r:g:b"
+fn extended_color<'a>(subparams: &[u16], iter: &mut impl Iterator<Item = &'a [u16]>) -> Option<CellColor> {
+    let mut next = || iter.next().and_then(|p| p.first().copied());
+    match subparams {
+        [5, index, ..] => Some(CellColor::Indexed(*index as u8)),
+        [2, _, r, g, b, ..] | [2, r, g, b] => Some(CellColor::Rgb(*r as u8, *g as u8, *b as u8)),
+        [] => match next()? {
+            5 => Some(CellColor::Indexed(next()? as u8)),
+            2 => Some(CellColor::Rgb(next()? as u8, next()? as u8, next()? as u8)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Line {
+    pub cells: Vec<Cell>,
+    // Set when the line was soft-wrapped into the next one rather than ended by a newline
+    pub wrapped: bool,
+}
+
+impl Line {
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    // Writes a cell at the column, padding with blanks if the line is shorter
+    pub fn put(&mut self, column: usize, cell: Cell) {
+        if column >= self.cells.len() {
+            self.cells.resize(column, Cell::default());
+            self.cells.push(cell);
+        } else {
+            self.cells[column] = cell;
+        }
+    }
+
+    // Blanks the columns in range, dropping trailing cells instead of storing blanks
+    pub fn erase(&mut self, start: usize, end: usize) {
+        if end >= self.cells.len() {
+            self.cells.truncate(start);
+            return;
+        }
+        for cell in &mut self.cells[start.min(end)..end] {
+            *cell = Cell::default();
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.cells.iter().map(|cell| cell.c).collect()
+    }
+
+    // Column ranges (start inclusive, end exclusive) covered by the given hyperlink
+    pub fn hyperlink_spans(&self, id: HyperlinkId) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut start = None;
+        for (column, cell) in self.cells.iter().enumerate() {
+            match (cell.hyperlink == Some(id), start) {
+                (true, None) => start = Some(column),
+                (false, Some(s)) => {
+                    spans.push((s, column));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            spans.push((s, self.cells.len()));
+        }
+        spans
+    }
+}
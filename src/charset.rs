@@ -0,0 +1,85 @@
+// G0-G3 character set designation (SCS) and locking shifts (SO/SI)
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    DecSpecialGraphics,
+    Uk,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CharsetState {
+    slots: [Charset; 4],
+    active: usize,
+}
+
+impl Charset {
+    pub fn from_designator(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(Charset::Ascii),
+            b'0' => Some(Charset::DecSpecialGraphics),
+            b'A' => Some(Charset::Uk),
+            _ => None,
+        }
+    }
+
+    fn map(self, c: char) -> char {
+        match self {
+            Charset::Ascii => c,
+            Charset::Uk if c == '#' => '£',
+            Charset::Uk => c,
+            Charset::DecSpecialGraphics => match c {
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+        }
+    }
+}
+
+impl CharsetState {
+    pub fn designate(&mut self, slot: usize, charset: Charset) {
+        if let Some(target) = self.slots.get_mut(slot) {
+            *target = charset;
+        }
+    }
+
+    // SO selects G1, SI selects G0
+    pub fn shift(&mut self, slot: usize) {
+        self.active = slot;
+    }
+
+    pub fn map(&self, c: char) -> char {
+        self.slots[self.active].map(c)
+    }
+}
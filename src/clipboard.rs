@@ -0,0 +1,160 @@
+// OSC 52 clipboard access for applications, gated by a per-session policy, and pasting into them
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardTarget {
+    Clipboard,
+    Primary,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardPolicy {
+    Allow,
+    Deny,
+    Ask,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Store { target: ClipboardTarget, text: String },
+    Load { target: ClipboardTarget, selector: String, terminator: &'static str },
+}
+
+impl ClipboardTarget {
+    // The reply echoes back the selector character the application asked for
+    fn selector(self) -> char {
+        match self {
+            ClipboardTarget::Clipboard => 'c',
+            ClipboardTarget::Primary => 'p',
+        }
+    }
+}
+
+// Parses "OSC 52 ; Pc ; Pd", where Pc lists selection buffers and Pd is base64 data or "?".
+// Read requests keep the string terminator so the reply can end the same way
+pub fn parse_osc52(selection: &[u8], data: &[u8], max_bytes: usize, terminator: &'static str) -> Vec<ClipboardRequest> {
+    let selection = String::from_utf8_lossy(selection);
+    // An empty selection means "s 0", which we map onto the regular clipboard
+    let selection = if selection.is_empty() { "s0".into() } else { selection };
+
+    let mut targets = Vec::new();
+    for c in selection.chars() {
+        let target = match c {
+            'c' | 's' | '0'..='7' => ClipboardTarget::Clipboard,
+            'p' => ClipboardTarget::Primary,
+            _ => continue,
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    if data == b"?" {
+        return targets
+            .into_iter()
+            .map(|target| ClipboardRequest::Load { target, selector: target.selector().to_string(), terminator })
+            .collect();
+    }
+
+    // Reject oversized payloads before decoding; base64 inflates data by a third
+    if data.len() / 4 * 3 > max_bytes {
+        eprintln!("OSC 52: clipboard payload of {} bytes exceeds limit", data.len());
+        return Vec::new();
+    }
+    let Some(text) = STANDARD.decode(data).ok().and_then(|bytes| String::from_utf8(bytes).ok()) else {
+        return Vec::new();
+    };
+
+    targets
+        .into_iter()
+        .map(|target| ClipboardRequest::Store { target, text: text.clone() })
+        .collect()
+}
+
+// Builds the OSC 52 reply to a read request, truncated to the size cap
+pub fn encode_reply(selector: &str, text: &str, max_bytes: usize, terminator: &str) -> Vec<u8> {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("\x1b]52;{};{}{}", selector, STANDARD.encode(&text[..end]), terminator).into_bytes()
+}
+
+// Drops control characters a paste could use to drive the terminal or break out of bracketed paste,
+// keeping tabs and turning any line ending into a newline
+pub fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .chars()
+        .map(|c| if c == '\r' { '\n' } else { c })
+        .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+        .collect()
+}
+
+// Bytes sent to the application for a sanitized paste, wrapped in markers when it enabled mode 2004
+pub fn encode_paste(text: &str, bracketed: bool) -> Vec<u8> {
+    if bracketed {
+        format!("\x1b[200~{}\x1b[201~", text).into_bytes()
+    } else {
+        text.as_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_decoded_text_once_per_target() {
+        let requests = parse_osc52(b"cps0", b"aGVsbG8=", 1024, "\x1b\\");
+        assert_eq!(
+            requests,
+            vec![
+                ClipboardRequest::Store { target: ClipboardTarget::Clipboard, text: "hello".to_string() },
+                ClipboardRequest::Store { target: ClipboardTarget::Primary, text: "hello".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn queries_echo_the_selector_and_terminator() {
+        let requests = parse_osc52(b"", b"?", 1024, "\x07");
+        assert_eq!(
+            requests,
+            vec![ClipboardRequest::Load {
+                target: ClipboardTarget::Clipboard,
+                selector: "c".to_string(),
+                terminator: "\x07",
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_and_invalid_payloads() {
+        assert!(parse_osc52(b"c", b"aGVsbG8=", 4, "\x1b\\").is_empty());
+        assert!(parse_osc52(b"c", b"not base64!", 1024, "\x1b\\").is_empty());
+        // Valid base64, but not UTF-8
+        assert!(parse_osc52(b"c", b"/w==", 1024, "\x1b\\").is_empty());
+    }
+
+    #[test]
+    fn truncates_replies_on_a_char_boundary() {
+        assert_eq!(encode_reply("c", "héllo", 2, "\x1b\\"), b"\x1b]52;c;aA==\x1b\\");
+        assert_eq!(encode_reply("p", "hi", 16, "\x07"), b"\x1b]52;p;aGk=\x07");
+    }
+
+    #[test]
+    fn sanitizes_pastes() {
+        assert_eq!(sanitize_paste("a\r\nb\rc\td"), "a\nb\nc\td");
+        // An embedded end marker loses its escape, so it can't end bracketed paste early
+        assert_eq!(sanitize_paste("x\x1b[201~rm -rf ~\x07\x7f"), "x[201~rm -rf ~");
+    }
+
+    #[test]
+    fn brackets_pastes_when_asked() {
+        assert_eq!(encode_paste("ls", true), b"\x1b[200~ls\x1b[201~");
+        assert_eq!(encode_paste("ls", false), b"ls");
+    }
+}
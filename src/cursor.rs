@@ -0,0 +1,45 @@
+// Cursor appearance selected with DECSCUSR (CSI Ps SP q)
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self { shape: CursorShape::Block, blinking: true }
+    }
+}
+
+impl CursorStyle {
+    pub fn from_decscusr(param: u16) -> Option<Self> {
+        let (shape, blinking) = match param {
+            0 | 1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => return None,
+        };
+        Some(Self { shape, blinking })
+    }
+
+    pub fn decscusr(&self) -> u16 {
+        let base = match self.shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        };
+        if self.blinking { base } else { base + 1 }
+    }
+}
@@ -0,0 +1,319 @@
+// Exports styled lines as plain text, ANSI text, standalone HTML or SVG
+
+use sdl2::pixels::Color;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cell::{Cell, Line, Style};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Text,
+    Ansi,
+    Html,
+    Svg,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportScope {
+    Screen,
+    Selection,
+    Scrollback,
+}
+
+// Everything needed to turn cells into colored output
+pub struct ExportTheme<'a> {
+    pub foreground: Color,
+    pub background: Color,
+    pub cell_size: (u32, u32),
+    pub font_size: u16,
+    pub resolve: &'a dyn Fn(&Cell) -> (Color, Option<Color>),
+}
+
+// Consecutive cells that render the same way
+struct Run {
+    text: String,
+    column: usize,
+    style: Style,
+    fg: Color,
+    bg: Option<Color>,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" | "txt" => Some(ExportFormat::Text),
+            "ansi" => Some(ExportFormat::Ansi),
+            "html" => Some(ExportFormat::Html),
+            "svg" => Some(ExportFormat::Svg),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Ansi => "ansi",
+            ExportFormat::Html => "html",
+            ExportFormat::Svg => "svg",
+        }
+    }
+}
+
+impl ExportScope {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "screen" => Some(ExportScope::Screen),
+            "selection" => Some(ExportScope::Selection),
+            "scrollback" | "all" => Some(ExportScope::Scrollback),
+            _ => None,
+        }
+    }
+}
+
+// Joins soft-wrapped rows back into the logical lines the application wrote
+pub fn rejoin(rows: Vec<Line>) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut continues = false;
+    for row in rows {
+        let wrapped = row.wrapped;
+        match lines.last_mut() {
+            Some(line) if continues => line.cells.extend(row.cells),
+            _ => lines.push(Line { cells: row.cells, wrapped: false }),
+        }
+        continues = wrapped;
+    }
+    lines
+}
+
+pub fn export(format: ExportFormat, lines: &[Line], theme: &ExportTheme) -> String {
+    match format {
+        ExportFormat::Text => lines.iter().map(|line| line.text().trim_end().to_string()).collect::<Vec<_>>().join("\n") + "\n",
+        ExportFormat::Ansi => export_ansi(lines),
+        ExportFormat::Html => export_html(lines, theme),
+        ExportFormat::Svg => export_svg(lines, theme),
+    }
+}
+
+fn runs(line: &Line, theme: &ExportTheme) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for (column, cell) in line.cells.iter().enumerate() {
+        let (fg, bg) = (theme.resolve)(cell);
+        match runs.last_mut() {
+            Some(run) if run.style == cell.style && run.fg == fg && run.bg == bg => run.text.push(cell.c),
+            _ => runs.push(Run { text: cell.c.to_string(), column, style: cell.style, fg, bg }),
+        }
+    }
+    // Trailing blanks without a background add nothing to the output
+    if let Some(last) = runs.last_mut() {
+        if last.bg.is_none() {
+            last.text.truncate(last.text.trim_end().len());
+        }
+    }
+    runs.retain(|run| !run.text.is_empty());
+    runs
+}
+
+// SGR sequences are only emitted when the style changes, and every line ends reset
+fn export_ansi(lines: &[Line]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let mut current = Style::default();
+        let text_end = line.cells.iter().rposition(|cell| cell.c != ' ' || cell.style != Style::default());
+        for cell in line.cells.iter().take(text_end.map_or(0, |end| end + 1)) {
+            if cell.style != current {
+                out.push_str(&format!("\x1b[{}m", cell.style.to_sgr()));
+                current = cell.style;
+            }
+            out.push(cell.c);
+        }
+        if current != Style::default() {
+            out.push_str("\x1b[0m");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn css_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn text_decoration(style: Style) -> Option<&'static str> {
+    match (style.underline, style.strikethrough) {
+        (true, true) => Some("underline line-through"),
+        (true, false) => Some("underline"),
+        (false, true) => Some("line-through"),
+        (false, false) => None,
+    }
+}
+
+fn export_html(lines: &[Line], theme: &ExportTheme) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Terminal output</title>\n</head>\n\
+         <body style=\"margin:0;background:{bg}\">\n<pre style=\"margin:0;padding:8px;font-family:monospace;\
+         font-size:{size}px;color:{fg};background:{bg}\">",
+        bg = css_color(theme.background),
+        fg = css_color(theme.foreground),
+        size = theme.font_size,
+    );
+
+    for line in lines {
+        for run in runs(line, theme) {
+            let mut css = Vec::new();
+            if run.fg != theme.foreground {
+                css.push(format!("color:{}", css_color(run.fg)));
+            }
+            if let Some(bg) = run.bg {
+                css.push(format!("background:{}", css_color(bg)));
+            }
+            if run.style.bold {
+                css.push("font-weight:bold".to_string());
+            }
+            if run.style.italic {
+                css.push("font-style:italic".to_string());
+            }
+            if let Some(decoration) = text_decoration(run.style) {
+                css.push(format!("text-decoration:{}", decoration));
+            }
+
+            let text = escape_markup(&run.text);
+            if css.is_empty() {
+                out.push_str(&text);
+            } else {
+                out.push_str(&format!("<span style=\"{}\">{}</span>", css.join(";"), text));
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+// One text element per run, positioned on the cell grid so columns line up in any viewer
+fn export_svg(lines: &[Line], theme: &ExportTheme) -> String {
+    let (cell_width, line_height) = theme.cell_size;
+    let columns = lines.iter().map(Line::len).max().unwrap_or(0).max(1) as u32;
+    let (width, height) = (columns * cell_width, lines.len().max(1) as u32 * line_height);
+    let baseline = line_height * 4 / 5;
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+         font-family=\"monospace\" font-size=\"{size}\" xml:space=\"preserve\">\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"{bg}\"/>\n",
+        w = width,
+        h = height,
+        size = theme.font_size,
+        bg = css_color(theme.background),
+    );
+
+    for (row, line) in lines.iter().enumerate() {
+        let y = row as u32 * line_height;
+        for run in runs(line, theme) {
+            let x = run.column as u32 * cell_width;
+            let run_width = run.text.chars().count() as u32 * cell_width;
+            if let Some(bg) = run.bg {
+                out.push_str(&format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                    x, y, run_width, line_height, css_color(bg)
+                ));
+            }
+            if run.text.trim().is_empty() {
+                continue;
+            }
+
+            let mut attributes = format!("x=\"{}\" y=\"{}\" textLength=\"{}\" fill=\"{}\"", x, y + baseline, run_width, css_color(run.fg));
+            if run.style.bold {
+                attributes.push_str(" font-weight=\"bold\"");
+            }
+            if run.style.italic {
+                attributes.push_str(" font-style=\"italic\"");
+            }
+            if let Some(decoration) = text_decoration(run.style) {
+                attributes.push_str(&format!(" text-decoration=\"{}\"", decoration));
+            }
+            out.push_str(&format!("<text {}>{}</text>\n", attributes, escape_markup(&run.text)));
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+// Saves an export under a timestamped name in the directory, without overwriting anything
+pub fn save(directory: &Path, format: ExportFormat, contents: &str) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    for attempt in 0..100 {
+        let suffix = if attempt == 0 { String::new() } else { format!("-{}", attempt) };
+        let path = directory.join(format!("terminal-{}{}.{}", stamp, suffix, format.extension()));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes()).map_err(|e| e.to_string())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Err(format!("No free file name in {}", directory.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(text: &str, wrapped: bool) -> Line {
+        Line { cells: text.chars().map(|c| Cell { c, ..Cell::default() }).collect(), wrapped }
+    }
+
+    fn export_with_theme(format: ExportFormat, lines: &[Line]) -> String {
+        let resolve = |_: &Cell| (Color::RGB(255, 255, 255), None);
+        let theme = ExportTheme {
+            foreground: Color::RGB(255, 255, 255),
+            background: Color::RGB(0, 0, 0),
+            cell_size: (8, 16),
+            font_size: 14,
+            resolve: &resolve,
+        };
+        export(format, lines, &theme)
+    }
+
+    #[test]
+    fn rejoins_soft_wrapped_rows() {
+        let lines = rejoin(vec![row("abc", true), row("def", true), row("gh", false), row("ij", false)]);
+        assert_eq!(lines, vec![row("abcdefgh", false), row("ij", false)]);
+    }
+
+    #[test]
+    fn ansi_emits_sgr_only_on_changes() {
+        let mut line = row("abc  ", false);
+        line.cells[0].style.bold = true;
+        line.cells[1].style.bold = true;
+        let mut last = row("x", false);
+        last.cells[0].style.bold = true;
+        assert_eq!(export_ansi(&[line, last]), "\x1b[0;1mab\x1b[0mc\n\x1b[0;1mx\x1b[0m\n");
+    }
+
+    #[test]
+    fn text_trims_lines_and_html_escapes_markup() {
+        assert_eq!(export_with_theme(ExportFormat::Text, &[row("a  ", false), row("<b>&", false)]), "a\n<b>&\n");
+        let html = export_with_theme(ExportFormat::Html, &[row("<b>&", false)]);
+        assert!(html.contains("&lt;b&gt;&amp;") && !html.contains("<b>"));
+    }
+}
@@ -0,0 +1,192 @@
+// Decoded images placed on the grid, anchored to absolute line ids so they scroll with the text
+
+use sdl2::image::ImageRWops;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rwops::RWops;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Straight RGBA pixels, row-major
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct ImagePlacement {
+    pub id: u64,
+    pub image_id: u32,     // kitty image id, 0 for images without one (sixel)
+    pub placement_id: u32, // kitty placement id within the image
+    pub image: Rc<ImageData>,
+    pub line: u64,
+    pub column: usize,
+    pub rows: usize,
+    pub columns: usize,
+    pub z_index: i32,
+    pub source: (u32, u32, u32, u32), // crop rectangle within the image
+    pub offset: (u32, u32),           // pixel offset within the first cell
+    pub size: (u32, u32),             // displayed size in pixels
+}
+
+// An image ready to composite, in window pixels
+pub struct VisibleImage {
+    pub id: u64,
+    pub image: Rc<ImageData>,
+    pub x: i32,
+    pub y: i32,
+    pub z_index: i32,
+    pub source: (u32, u32, u32, u32),
+    pub size: (u32, u32),
+}
+
+#[derive(Default)]
+pub struct ImageStore {
+    placements: Vec<ImagePlacement>,
+    // Transmitted images that can be placed by id, e.g. through the kitty protocol
+    stored: HashMap<u32, Rc<ImageData>>,
+    next_id: u64,
+}
+
+impl ImageData {
+    // Decodes PNG, JPEG, GIF and the other formats SDL2_image understands, refusing images whose
+    // pixels would take more than `max_bytes` however small the encoded file is
+    pub fn decode(bytes: &[u8], max_bytes: usize) -> Result<Self, String> {
+        let surface = RWops::from_bytes(bytes)?.load()?;
+        let size = (surface.width() as usize).checked_mul(surface.height() as usize).and_then(|pixels| pixels.checked_mul(4));
+        if size.is_none_or(|size| size > max_bytes) {
+            return Err(format!("{}x{} image exceeds the {} byte limit", surface.width(), surface.height(), max_bytes));
+        }
+        let surface = surface.convert_format(PixelFormatEnum::RGBA32)?;
+        let (width, height) = (surface.width(), surface.height());
+        let pitch = surface.pitch() as usize;
+        let row_bytes = width as usize * 4;
+
+        let mut pixels = Vec::with_capacity(row_bytes * height as usize);
+        surface.with_lock(|buffer| {
+            for row in buffer.chunks(pitch).take(height as usize) {
+                pixels.extend_from_slice(&row[..row_bytes]);
+            }
+        });
+        Ok(Self { width, height, pixels })
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.pixels.len()
+    }
+}
+
+impl ImagePlacement {
+    // A placement showing the whole image at its natural size
+    pub fn new(image: Rc<ImageData>, line: u64, column: usize, cell_size: (u32, u32)) -> Self {
+        let (width, height) = (image.width, image.height);
+        Self {
+            id: 0,
+            image_id: 0,
+            placement_id: 0,
+            image,
+            line,
+            column,
+            rows: height.div_ceil(cell_size.1).max(1) as usize,
+            columns: width.div_ceil(cell_size.0).max(1) as usize,
+            z_index: 0,
+            source: (0, 0, width, height),
+            offset: (0, 0),
+            size: (width, height),
+        }
+    }
+}
+
+impl ImageStore {
+    // Places an image and evicts the oldest placements once the screen exceeds its memory budget
+    pub fn place(&mut self, mut placement: ImagePlacement, memory_limit: usize) -> u64 {
+        self.next_id += 1;
+        placement.id = self.next_id;
+
+        // A kitty placement replaces the previous one with the same image and placement id
+        if placement.image_id != 0 && placement.placement_id != 0 {
+            self.placements.retain(|p| (p.image_id, p.placement_id) != (placement.image_id, placement.placement_id));
+        }
+        self.placements.push(placement);
+
+        while self.memory_usage() > memory_limit && self.placements.len() > 1 {
+            self.placements.remove(0);
+        }
+        self.next_id
+    }
+
+    pub fn store(&mut self, image_id: u32, image: ImageData, memory_limit: usize) -> Rc<ImageData> {
+        let image = Rc::new(image);
+        self.stored.insert(image_id, Rc::clone(&image));
+        while self.memory_usage() > memory_limit {
+            // Drop stored images nothing is showing before touching visible placements
+            let unused = self.stored.keys()
+                .find(|&&id| id != image_id && !self.placements.iter().any(|p| p.image_id == id))
+                .copied();
+            match unused {
+                Some(id) => {
+                    self.stored.remove(&id);
+                }
+                None if self.placements.len() > 1 => {
+                    self.placements.remove(0);
+                }
+                None => break,
+            }
+        }
+        image
+    }
+
+    pub fn stored(&self, image_id: u32) -> Option<Rc<ImageData>> {
+        self.stored.get(&image_id).cloned()
+    }
+
+    // Removes matching placements, also freeing the image data of affected images when asked to
+    pub fn delete_where(&mut self, free_data: bool, predicate: impl Fn(&ImagePlacement) -> bool) {
+        let mut affected = Vec::new();
+        self.placements.retain(|placement| {
+            let matched = predicate(placement);
+            if matched {
+                affected.push(placement.image_id);
+            }
+            !matched
+        });
+        if free_data {
+            for image_id in affected {
+                if !self.placements.iter().any(|p| p.image_id == image_id) {
+                    self.stored.remove(&image_id);
+                }
+            }
+        }
+    }
+
+    pub fn delete_image(&mut self, image_id: u32, free_data: bool) {
+        self.delete_where(free_data, |placement| placement.image_id == image_id);
+        if free_data {
+            self.stored.remove(&image_id);
+        }
+    }
+
+    // Drops images whose last row has scrolled out of the history
+    pub fn evict_before(&mut self, first_line: u64) {
+        self.placements.retain(|placement| placement.line + placement.rows as u64 > first_line);
+    }
+
+    pub fn clear(&mut self) {
+        self.placements.clear();
+    }
+
+    pub fn placements(&self) -> &[ImagePlacement] {
+        &self.placements
+    }
+
+    // Stored images are shared with their placements, so count each image once
+    pub fn memory_usage(&self) -> usize {
+        let stored: usize = self.stored.values().map(|image| image.byte_size()).sum();
+        let unstored: usize = self.placements
+            .iter()
+            .filter(|placement| !self.stored.values().any(|image| Rc::ptr_eq(image, &placement.image)))
+            .map(|placement| placement.image.byte_size())
+            .sum();
+        stored + unstored
+    }
+}
@@ -0,0 +1,191 @@
+// Hints mode: labels every regex match on screen so it can be acted on by typing its label
+
+use regex::Regex;
+
+use super::smart_select::{GIT_SHA_PATTERN, PATH_PATTERN, URL_PATTERN};
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintAction {
+    Copy,
+    Open,
+    Paste,
+    Edit,
+}
+
+#[derive(Clone, Debug)]
+pub struct HintRule {
+    pub pattern: String,
+    pub action: HintAction,
+}
+
+// A labelled match starting at (absolute line id, column)
+#[derive(Clone, Debug)]
+pub struct Hint {
+    pub label: String,
+    pub start: (u64, usize),
+    pub text: String,
+    pub action: HintAction,
+}
+
+pub struct Hints {
+    pub hints: Vec<Hint>,
+    pub typed: String,
+}
+
+pub enum HintInput {
+    Pending,
+    Cancelled,
+    Chosen(Hint),
+}
+
+impl HintRule {
+    fn new(pattern: &str, action: HintAction) -> Self {
+        Self { pattern: pattern.to_string(), action }
+    }
+}
+
+// Earlier rules win where matches overlap
+pub fn default_rules() -> Vec<HintRule> {
+    vec![
+        HintRule::new(URL_PATTERN, HintAction::Open),
+        HintRule::new(PATH_PATTERN, HintAction::Edit),
+        HintRule::new(GIT_SHA_PATTERN, HintAction::Copy),
+    ]
+}
+
+pub fn compile(rules: &[HintRule]) -> Vec<(Regex, HintAction)> {
+    rules
+        .iter()
+        .filter_map(|rule| match Regex::new(&rule.pattern) {
+            Ok(regex) => Some((regex, rule.action)),
+            Err(e) => {
+                eprintln!("Hint rule '{}': {}", rule.pattern, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// Non-overlapping [start, end) character ranges matched in a line, with the action of their rule
+pub fn find(rules: &[(Regex, HintAction)], chars: &[char]) -> Vec<(usize, usize, HintAction)> {
+    let text: String = chars.iter().collect();
+    let byte_offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    let to_column = |offset: usize| byte_offsets.partition_point(|&start| start < offset);
+
+    let mut found: Vec<(usize, usize, HintAction)> = Vec::new();
+    for (regex, action) in rules {
+        for m in regex.find_iter(&text) {
+            let (start, end) = (to_column(m.start()), to_column(m.end()));
+            if start < end && !found.iter().any(|&(s, e, _)| start < e && s < end) {
+                found.push((start, end, *action));
+            }
+        }
+    }
+    found.sort_by_key(|&(start, _, _)| start);
+    found
+}
+
+// Labels of equal length, so typing one never completes another on the way
+pub fn labels(count: usize, alphabet: &str) -> Vec<String> {
+    let alphabet: Vec<char> = alphabet.chars().collect();
+    if alphabet.len() < 2 {
+        return Vec::new();
+    }
+    let mut length = 1;
+    while alphabet.len().pow(length) < count {
+        length += 1;
+    }
+    (0..count)
+        .map(|mut index| {
+            let mut label = vec![alphabet[0]; length as usize];
+            for slot in label.iter_mut().rev() {
+                *slot = alphabet[index % alphabet.len()];
+                index /= alphabet.len();
+            }
+            label.into_iter().collect()
+        })
+        .collect()
+}
+
+impl Hints {
+    // Narrows the hints by a typed character, choosing one once its whole label is typed
+    pub fn input(&mut self, c: char) -> HintInput {
+        self.typed.push(c);
+        if let Some(hint) = self.hints.iter().find(|hint| hint.label == self.typed) {
+            return HintInput::Chosen(hint.clone());
+        }
+        if self.hints.iter().any(|hint| hint.label.starts_with(&self.typed)) {
+            HintInput::Pending
+        } else {
+            HintInput::Cancelled
+        }
+    }
+}
+
+// Splits "path:line:column" into its parts, leaving paths without a location alone
+pub fn file_location(text: &str) -> (&str, Option<u32>) {
+    let mut parts = text.splitn(3, ':');
+    let path = parts.next().unwrap_or(text);
+    match parts.next().and_then(|line| line.parse().ok()) {
+        Some(line) => (path, Some(line)),
+        None => (text, None),
+    }
+}
+
+// Command line for opening a file in the editor, at the line when there is one
+pub fn editor_command(editor: &str, text: &str) -> String {
+    let (path, line) = file_location(text);
+    // Quoted against the shell, except a leading ~ which the shell should still expand
+    let (home, path) = match path.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", path),
+    };
+    let quoted = format!("{}'{}'", home, path.replace('\'', r"'\''"));
+    match line {
+        Some(line) => format!("{} +{} {}", editor, line, quoted),
+        None => format!("{} {}", editor, quoted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hint(label: &str) -> Hint {
+        Hint { label: label.to_string(), start: (0, 0), text: label.to_string(), action: HintAction::Copy }
+    }
+
+    #[test]
+    fn labels_have_equal_length() {
+        assert_eq!(labels(3, "ab"), vec!["aa", "ab", "ba"]);
+        assert_eq!(labels(2, "abc"), vec!["a", "b"]);
+        assert!(labels(5, "a").is_empty());
+    }
+
+    #[test]
+    fn typing_narrows_then_chooses_or_cancels() {
+        let mut hints = Hints { hints: vec![hint("aa"), hint("ab")], typed: String::new() };
+        assert!(matches!(hints.input('a'), HintInput::Pending));
+        assert!(matches!(hints.input('b'), HintInput::Chosen(chosen) if chosen.label == "ab"));
+        let mut hints = Hints { hints: vec![hint("aa")], typed: String::new() };
+        assert!(matches!(hints.input('x'), HintInput::Cancelled));
+    }
+
+    #[test]
+    fn editor_command_quotes_the_path_and_passes_the_line() {
+        assert_eq!(editor_command("vim", "src/main.rs:12:4"), "vim +12 'src/main.rs'");
+        assert_eq!(editor_command("vim", "~/it's here.txt"), r"vim ~/'it'\''s here.txt'");
+        assert_eq!(editor_command("code -g", "$(rm -rf ~)"), "code -g '$(rm -rf ~)'");
+        // Without a numeric line the whole text is the path
+        assert_eq!(file_location("a:b"), ("a:b", None));
+    }
+
+    #[test]
+    fn earlier_rules_win_overlapping_matches() {
+        let rules = compile(&default_rules());
+        let chars: Vec<char> = "open https://x.org/a/b and 1ceed26".chars().collect();
+        let found = find(&rules, &chars);
+        assert_eq!(found, vec![(5, 22, HintAction::Open), (27, 34, HintAction::Copy)]);
+    }
+}
@@ -0,0 +1,88 @@
+// OSC 8 hyperlinks, interned so each cell only carries a small id
+
+use std::collections::{HashMap, VecDeque};
+use std::process::Command;
+use std::thread;
+
+// Links interned last are kept; cells still pointing at older ones simply lose their link
+const MAX_LINKS: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HyperlinkId(pub u32);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Hyperlink {
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HyperlinkRegistry {
+    links: HashMap<HyperlinkId, Hyperlink>,
+    lookup: HashMap<Hyperlink, HyperlinkId>,
+    // Ids by when they were last interned, oldest first
+    order: VecDeque<HyperlinkId>,
+    next_id: u32,
+}
+
+impl HyperlinkRegistry {
+    // Parses the "key=value:key=value" OSC 8 parameters and interns the link.
+    // Links sharing an explicit id and URI are the same link even when split across lines.
+    pub fn intern(&mut self, params: &str, uri: &str) -> HyperlinkId {
+        let id = params
+            .split(':')
+            .find_map(|param| param.strip_prefix("id="))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        let link = Hyperlink { id, uri: uri.to_string() };
+
+        if let Some(&existing) = self.lookup.get(&link) {
+            // Anonymous links are only shared between consecutive runs of output
+            if link.id.is_some() || self.order.back() == Some(&existing) {
+                if let Some(position) = self.order.iter().position(|&id| id == existing) {
+                    self.order.remove(position);
+                }
+                self.order.push_back(existing);
+                return existing;
+            }
+        }
+
+        let handle = HyperlinkId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.links.insert(handle, link.clone());
+        self.lookup.insert(link, handle);
+        self.order.push_back(handle);
+
+        while self.order.len() > MAX_LINKS {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(link) = self.links.remove(&oldest) {
+                // A later anonymous link with the same URI may own the lookup entry by now
+                if self.lookup.get(&link) == Some(&oldest) {
+                    self.lookup.remove(&link);
+                }
+            }
+        }
+        handle
+    }
+
+    pub fn get(&self, id: HyperlinkId) -> Option<&Hyperlink> {
+        self.links.get(&id)
+    }
+}
+
+// Launches the configured opener with the URI as a single argument, never through a shell
+pub fn open_uri(opener: &str, uri: &str) -> Result<(), String> {
+    let mut parts = opener.split_whitespace();
+    let program = parts.next().ok_or_else(|| String::from("No link opener configured"))?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .arg(uri)
+        .spawn()
+        .map_err(|e| format!("Failed to launch {}: {}", program, e))?;
+
+    // Reap the opener in the background so it doesn't linger as a zombie
+    thread::spawn(move || child.wait());
+    Ok(())
+}
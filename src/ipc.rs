@@ -0,0 +1,189 @@
+// Control socket for scripting a running terminal, plus the command line client that talks to it
+//
+// Requests are a single line ("export html scrollback", "status"); replies start with "ok" or
+// "error: <message>" on their own line, followed by the body.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::export::{ExportFormat, ExportScope};
+
+pub const SOCKET_ENV: &str = "MIKOSHI_SOCKET";
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(200);
+// Requests are a few words, so anything longer is cut off and rejected by the parser
+const MAX_REQUEST_BYTES: u64 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpcRequest {
+    Export { format: ExportFormat, scope: ExportScope },
+    Status,
+}
+
+type Received = (Result<IpcRequest, String>, UnixStream);
+
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+    // Requests read by the per-connection threads, waiting for the UI thread
+    received: Receiver<Received>,
+    sender: Sender<Received>,
+}
+
+impl IpcRequest {
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["export", rest @ ..] => {
+                let format = rest.first().copied().unwrap_or("text");
+                let scope = rest.get(1).copied().unwrap_or("screen");
+                Ok(IpcRequest::Export {
+                    format: ExportFormat::from_name(format).ok_or(format!("unknown format '{}'", format))?,
+                    scope: ExportScope::from_name(scope).ok_or(format!("unknown scope '{}'", scope))?,
+                })
+            }
+            ["status"] => Ok(IpcRequest::Status),
+            _ => Err(format!("unknown request '{}'", line.trim())),
+        }
+    }
+}
+
+impl IpcServer {
+    // Listens in $XDG_RUNTIME_DIR (or the temp dir) and exports the path for child processes
+    pub fn bind() -> Result<Self, String> {
+        let directory = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+        let path = directory.join(format!("mikoshi-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // The socket hands out the scrollback, so only the user may connect whatever the umask
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        std::env::set_var(SOCKET_ENV, &path);
+        let (sender, received) = mpsc::channel();
+        Ok(Self { listener, path, received, sender })
+    }
+
+    // Hands pending clients to reader threads, then returns a request that has been read, if any,
+    // with the connection to answer on. Slow clients never hold up the caller.
+    pub fn poll(&self) -> Option<(Result<IpcRequest, String>, UnixStream)> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+                let mut line = String::new();
+                if BufReader::new((&stream).take(MAX_REQUEST_BYTES)).read_line(&mut line).is_ok() {
+                    let _ = sender.send((IpcRequest::parse(&line), stream));
+                }
+            });
+        }
+        self.received.try_recv().ok()
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Writes the reply off the UI thread, since a client may read a large export slowly
+pub fn respond(mut stream: UnixStream, reply: Result<String, String>) {
+    thread::spawn(move || {
+        let message = match reply {
+            Ok(body) => format!("ok\n{}", body),
+            Err(e) => format!("error: {}\n", e),
+        };
+        let _ = stream.write_all(message.as_bytes());
+    });
+}
+
+const USAGE: &str = "usage: mikoshi export [--format text|ansi|html|svg] [--scope screen|selection|scrollback] \
+                     [--output FILE] [--socket PATH]\n       mikoshi status [--socket PATH]";
+
+// `mikoshi export ...` and `mikoshi status` talk to the terminal named by $MIKOSHI_SOCKET or --socket
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let (command, options) = args.split_first().ok_or(USAGE)?;
+    let mut format = "text".to_string();
+    let mut scope = "screen".to_string();
+    let mut output: Option<PathBuf> = None;
+    let mut socket = std::env::var_os(SOCKET_ENV).map(PathBuf::from);
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().cloned().ok_or(format!("{} needs a value\n{}", option, USAGE));
+        match option.as_str() {
+            "--format" | "-f" => format = value()?,
+            "--scope" | "-s" => scope = value()?,
+            "--output" | "-o" => output = Some(PathBuf::from(value()?)),
+            "--socket" => socket = Some(PathBuf::from(value()?)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let request = match command.as_str() {
+        "export" => format!("export {} {}\n", format, scope),
+        "status" => "status\n".to_string(),
+        _ => return Err(USAGE.to_string()),
+    };
+    let socket = socket.ok_or(format!("not running inside the terminal; set {} or pass --socket", SOCKET_ENV))?;
+    let mut stream = UnixStream::connect(&socket).map_err(|e| format!("{}: {}", socket.display(), e))?;
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(|e| e.to_string())?;
+    if let Some(error) = status.strip_prefix("error: ") {
+        return Err(error.trim_end().to_string());
+    }
+
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).map_err(|e| e.to_string())?;
+    match output {
+        Some(path) => fs::write(&path, &body).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => io::stdout().write_all(&body).map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(IpcRequest::parse("status\n"), Ok(IpcRequest::Status));
+        assert_eq!(
+            IpcRequest::parse("export svg scrollback"),
+            Ok(IpcRequest::Export { format: ExportFormat::Svg, scope: ExportScope::Scrollback })
+        );
+        assert!(IpcRequest::parse("export pdf").is_err());
+    }
+
+    #[test]
+    fn silent_clients_do_not_block_polling() {
+        let server = IpcServer::bind().unwrap();
+        let mode = fs::metadata(&server.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let _silent = UnixStream::connect(&server.path).unwrap();
+        let mut client = UnixStream::connect(&server.path).unwrap();
+        client.write_all(b"status\n").unwrap();
+
+        let started = Instant::now();
+        let mut received = server.poll();
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+        while received.is_none() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+            received = server.poll();
+        }
+        let (request, _) = received.unwrap();
+        assert_eq!(request, Ok(IpcRequest::Status));
+    }
+}
@@ -0,0 +1,189 @@
+// iTerm2 file transfers: OSC 1337 ; File = key=value;... : base64 ST
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+    Percent(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct FileTransfer {
+    pub name: Option<String>,
+    pub inline: bool,
+    pub width: Dimension,
+    pub height: Dimension,
+    pub preserve_aspect_ratio: bool,
+    pub move_cursor: bool,
+    pub data: Vec<u8>,
+}
+
+impl Dimension {
+    fn parse(value: &str) -> Dimension {
+        let number = |digits: &str| digits.trim().parse::<u32>().ok();
+        if value == "auto" {
+            Dimension::Auto
+        } else if let Some(pixels) = value.strip_suffix("px").and_then(number) {
+            Dimension::Pixels(pixels)
+        } else if let Some(percent) = value.strip_suffix('%').and_then(number) {
+            Dimension::Percent(percent)
+        } else {
+            number(value).map_or(Dimension::Auto, Dimension::Cells)
+        }
+    }
+
+    // Pixels along this axis, given the cell size and the full extent of the screen
+    fn resolve(self, cell: u32, screen: u32) -> Option<u32> {
+        match self {
+            Dimension::Auto => None,
+            Dimension::Cells(cells) => Some(cells.saturating_mul(cell)),
+            Dimension::Pixels(pixels) => Some(pixels),
+            Dimension::Percent(percent) => Some((screen as u64 * percent.min(100) as u64 / 100) as u32),
+        }
+    }
+}
+
+// Parses everything after "1337;", with the separators vte split on already put back
+pub fn parse_file(args: &[u8], max_bytes: usize) -> Option<FileTransfer> {
+    let args = args.strip_prefix(b"File=")?;
+    let split = args.iter().position(|&b| b == b':')?;
+    let (keys, payload) = (String::from_utf8_lossy(&args[..split]), &args[split + 1..]);
+
+    if payload.len() / 4 * 3 > max_bytes {
+        eprintln!("OSC 1337: file of {} encoded bytes exceeds limit", payload.len());
+        return None;
+    }
+    let data = STANDARD.decode(payload).ok()?;
+
+    let mut transfer = FileTransfer {
+        name: None,
+        inline: false,
+        width: Dimension::Auto,
+        height: Dimension::Auto,
+        preserve_aspect_ratio: true,
+        move_cursor: true,
+        data,
+    };
+    for pair in keys.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        match key {
+            // The name is base64 too, so it can carry any byte
+            "name" => {
+                transfer.name = STANDARD.decode(value).ok().map(|name| String::from_utf8_lossy(&name).into_owned());
+            }
+            "inline" => transfer.inline = value == "1",
+            "width" => transfer.width = Dimension::parse(value),
+            "height" => transfer.height = Dimension::parse(value),
+            "preserveAspectRatio" => transfer.preserve_aspect_ratio = value != "0",
+            "doNotMoveCursor" => transfer.move_cursor = value != "1",
+            _ => {}
+        }
+    }
+    Some(transfer)
+}
+
+impl FileTransfer {
+    // Displayed size in pixels for an image of the given natural size
+    pub fn display_size(&self, natural: (u32, u32), cell_size: (u32, u32), screen: (u32, u32)) -> (u32, u32) {
+        let (natural_width, natural_height) = (natural.0.max(1) as u64, natural.1.max(1) as u64);
+        let width = self.width.resolve(cell_size.0, screen.0);
+        let height = self.height.resolve(cell_size.1, screen.1);
+
+        let (width, height) = match (width, height) {
+            (None, None) => (natural_width, natural_height),
+            (Some(w), None) => (w as u64, w as u64 * natural_height / natural_width),
+            (None, Some(h)) => (h as u64 * natural_width / natural_height, h as u64),
+            // Both given: fit inside the box when keeping the aspect ratio, stretch otherwise
+            (Some(w), Some(h)) if self.preserve_aspect_ratio => {
+                let (w, h) = (w as u64, h as u64);
+                if w * natural_height <= h * natural_width {
+                    (w, w * natural_height / natural_width)
+                } else {
+                    (h * natural_width / natural_height, h)
+                }
+            }
+            (Some(w), Some(h)) => (w as u64, h as u64),
+        };
+        (width.clamp(1, u32::MAX as u64) as u32, height.clamp(1, u32::MAX as u64) as u32)
+    }
+
+    // Only the final path component of the sender's name is used, falling back to a generic one
+    pub fn file_name(&self) -> String {
+        self.name
+            .as_deref()
+            .and_then(|name| Path::new(name).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .unwrap_or_else(|| "download".to_string())
+    }
+}
+
+// Writes the file into the directory without overwriting anything, returning where it went
+pub fn save_download(directory: &Path, transfer: &FileTransfer) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    let name = transfer.file_name();
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem.to_string(), format!(".{}", extension)),
+        _ => (name.clone(), String::new()),
+    };
+
+    for attempt in 0..1000 {
+        let candidate = if attempt == 0 { name.clone() } else { format!("{}-{}{}", stem, attempt, extension) };
+        let path = directory.join(candidate);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(&transfer.data).map_err(|e| e.to_string())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Err(format!("No free file name for {} in {}", name, directory.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(keys: &str) -> FileTransfer {
+        parse_file(format!("File={}:aGk=", keys).as_bytes(), 1024).unwrap()
+    }
+
+    #[test]
+    fn parses_keys_and_payload() {
+        let transfer = transfer("inline=1;width=10;height=50%;doNotMoveCursor=1");
+        assert!(transfer.inline && !transfer.move_cursor);
+        assert_eq!((transfer.width, transfer.height), (Dimension::Cells(10), Dimension::Percent(50)));
+        assert_eq!(transfer.data, b"hi");
+        assert!(parse_file(b"File=inline=1:aGk=", 1).is_none());
+    }
+
+    #[test]
+    fn sizes_images_from_cells_percentages_and_pixels() {
+        let (cell, screen, natural) = ((8, 16), (800, 600), (200, 100));
+        assert_eq!(transfer("").display_size(natural, cell, screen), (200, 100));
+        // One side given keeps the aspect ratio
+        assert_eq!(transfer("width=10").display_size(natural, cell, screen), (80, 40));
+        // Both given fits inside the box, unless the aspect ratio may change
+        assert_eq!(transfer("width=50%;height=100px").display_size(natural, cell, screen), (200, 100));
+        let stretched = transfer("width=50%;height=100px;preserveAspectRatio=0");
+        assert_eq!(stretched.display_size(natural, cell, screen), (400, 100));
+    }
+
+    #[test]
+    fn keeps_only_the_last_name_component() {
+        assert_eq!(transfer("name=Li4vLi4vZXRjL3Bhc3N3ZA==").file_name(), "passwd");
+        assert_eq!(transfer("name=Li4vLmJhc2hyYw==").file_name(), "download");
+        assert_eq!(transfer("").file_name(), "download");
+    }
+}
@@ -0,0 +1,419 @@
+// Kitty graphics protocol (APC _G <control data> ; <payload> ST)
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::graphics::{ImageData, ImagePlacement, ImageStore};
+
+const SHM_DIRECTORY: &str = "/dev/shm";
+
+#[derive(Clone, Debug)]
+pub struct Control {
+    pub action: char,
+    pub format: u32,
+    pub medium: char,
+    pub compression: Option<char>,
+    pub data_width: u32,
+    pub data_height: u32,
+    pub data_size: usize,
+    pub data_offset: u64,
+    pub image_id: u32,
+    pub image_number: u32,
+    pub placement_id: u32,
+    pub more: bool,
+    pub quiet: u32,
+    pub source: (u32, u32, u32, u32),
+    pub cell_offset: (u32, u32),
+    pub columns: u32,
+    pub rows: u32,
+    pub z_index: i32,
+    pub stay: bool,
+    pub delete: char,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Self {
+            action: 't',
+            format: 32,
+            medium: 'd',
+            compression: None,
+            data_width: 0,
+            data_height: 0,
+            data_size: 0,
+            data_offset: 0,
+            image_id: 0,
+            image_number: 0,
+            placement_id: 0,
+            more: false,
+            quiet: 0,
+            source: (0, 0, 0, 0),
+            cell_offset: (0, 0),
+            columns: 0,
+            rows: 0,
+            z_index: 0,
+            stay: false,
+            delete: 'a',
+        }
+    }
+}
+
+// Where the command is happening, in the coordinates the image store uses
+pub struct Context {
+    pub cursor_line: u64,
+    pub cursor_column: usize,
+    pub first_visible_line: u64,
+    pub cell_size: (u32, u32),
+    pub max_image_bytes: usize,
+    pub memory_limit: usize,
+}
+
+// What the terminal has to do after a command: reply, and move the cursor past a new placement
+#[derive(Default)]
+pub struct Outcome {
+    pub reply: Option<String>,
+    pub cursor_advance: Option<(usize, usize)>,
+}
+
+#[derive(Default)]
+pub struct KittyGraphics {
+    // A chunked transmission in progress: the first chunk's control data and the base64 so far
+    pending: Option<(Control, Vec<u8>)>,
+    image_numbers: HashMap<u32, u32>,
+    next_image_id: u32,
+}
+
+fn parse_control(control: &str) -> Control {
+    let mut parsed = Control::default();
+    for pair in control.split(',') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let number = || value.parse::<u32>().unwrap_or(0);
+        let first_char = value.chars().next().unwrap_or('\0');
+        match key {
+            "a" => parsed.action = first_char,
+            "f" => parsed.format = number(),
+            "t" => parsed.medium = first_char,
+            "o" => parsed.compression = Some(first_char),
+            "s" => parsed.data_width = number(),
+            "v" => parsed.data_height = number(),
+            "S" => parsed.data_size = number() as usize,
+            "O" => parsed.data_offset = number() as u64,
+            "i" => parsed.image_id = number(),
+            "I" => parsed.image_number = number(),
+            "p" => parsed.placement_id = number(),
+            "m" => parsed.more = number() == 1,
+            "q" => parsed.quiet = number(),
+            "x" => parsed.source.0 = number(),
+            "y" => parsed.source.1 = number(),
+            "w" => parsed.source.2 = number(),
+            "h" => parsed.source.3 = number(),
+            "X" => parsed.cell_offset.0 = number(),
+            "Y" => parsed.cell_offset.1 = number(),
+            "c" => parsed.columns = number(),
+            "r" => parsed.rows = number(),
+            "z" => parsed.z_index = value.parse().unwrap_or(0),
+            "C" => parsed.stay = number() == 1,
+            "d" => parsed.delete = first_char,
+            _ => {}
+        }
+    }
+    parsed
+}
+
+impl KittyGraphics {
+    // Handles one APC body, starting with the 'G' that identifies graphics commands
+    pub fn handle(&mut self, body: &[u8], store: &mut ImageStore, context: &Context) -> Outcome {
+        let Some(body) = body.strip_prefix(b"G") else {
+            return Outcome::default();
+        };
+        let (control, payload) = match body.iter().position(|&b| b == b';') {
+            Some(split) => (&body[..split], &body[split + 1..]),
+            None => (body, &body[body.len()..]),
+        };
+        let control = parse_control(&String::from_utf8_lossy(control));
+
+        // Continuation chunks only carry "m" (and maybe "q"); the first chunk's keys apply
+        let (control, payload) = match self.pending.take() {
+            Some((first, mut data)) => {
+                if data.len() + payload.len() > context.max_image_bytes * 4 / 3 + 4 {
+                    return respond(&first, Err("EFBIG:image data too large".to_string()));
+                }
+                data.extend_from_slice(payload);
+                if control.more {
+                    self.pending = Some((first, data));
+                    return Outcome::default();
+                }
+                (first, data)
+            }
+            None if control.more => {
+                self.pending = Some((control, payload.to_vec()));
+                return Outcome::default();
+            }
+            None => (control, payload.to_vec()),
+        };
+
+        match control.action {
+            't' | 'T' | 'q' => self.transmit(control, &payload, store, context),
+            'p' => self.put(control, store, context),
+            'd' => {
+                self.delete(&control, store, context);
+                Outcome::default()
+            }
+            _ => respond(&control, Err("EINVAL:unsupported action".to_string())),
+        }
+    }
+
+    fn transmit(&mut self, mut control: Control, payload: &[u8], store: &mut ImageStore, context: &Context) -> Outcome {
+        let image = load_data(&control, payload, context.max_image_bytes).and_then(|data| decode(&control, &data, context.max_image_bytes));
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => return respond(&control, Err(e)),
+        };
+
+        // A query only checks that the image could be loaded
+        if control.action == 'q' {
+            return respond(&control, Ok(()));
+        }
+
+        if control.image_id == 0 {
+            // Anonymous transmissions never get a response, even under the id assigned here
+            if control.image_number == 0 {
+                control.quiet = 2;
+            }
+            self.next_image_id = self.next_image_id.wrapping_add(1).max(1);
+            control.image_id = self.next_image_id | 0x8000_0000;
+            if control.image_number != 0 {
+                self.image_numbers.insert(control.image_number, control.image_id);
+            }
+        }
+        store.store(control.image_id, image, context.memory_limit);
+
+        if control.action == 'T' {
+            return self.put(control, store, context);
+        }
+        respond(&control, Ok(()))
+    }
+
+    fn put(&mut self, mut control: Control, store: &mut ImageStore, context: &Context) -> Outcome {
+        if control.image_id == 0 {
+            control.image_id = self.image_numbers.get(&control.image_number).copied().unwrap_or(0);
+        }
+        let Some(image) = store.stored(control.image_id) else {
+            return respond(&control, Err("ENOENT:image not found".to_string()));
+        };
+
+        let mut placement = ImagePlacement::new(image, context.cursor_line, context.cursor_column, context.cell_size);
+        let (image_width, image_height) = (placement.image.width, placement.image.height);
+        let (x, y, w, h) = control.source;
+        let x = x.min(image_width);
+        let y = y.min(image_height);
+        let w = if w == 0 { image_width - x } else { w.min(image_width - x) };
+        let h = if h == 0 { image_height - y } else { h.min(image_height - y) };
+        if w == 0 || h == 0 {
+            return respond(&control, Err("EINVAL:empty source rectangle".to_string()));
+        }
+
+        // Columns/rows scale the image into that many cells; with only one given, keep the aspect ratio
+        let (cell_width, cell_height) = context.cell_size;
+        let scale = |length: u32, numerator: u32, denominator: u32| {
+            (length as u64 * numerator as u64 / denominator as u64).min(u32::MAX as u64) as u32
+        };
+        let (width, height) = match (control.columns, control.rows) {
+            (0, 0) => (w, h),
+            (c, 0) => (c.saturating_mul(cell_width), scale(c.saturating_mul(cell_width), h, w)),
+            (0, r) => (scale(r.saturating_mul(cell_height), w, h), r.saturating_mul(cell_height)),
+            (c, r) => (c.saturating_mul(cell_width), r.saturating_mul(cell_height)),
+        };
+        let offset = (
+            control.cell_offset.0.min(cell_width.saturating_sub(1)),
+            control.cell_offset.1.min(cell_height.saturating_sub(1)),
+        );
+
+        placement.image_id = control.image_id;
+        placement.placement_id = control.placement_id;
+        placement.z_index = control.z_index;
+        placement.source = (x, y, w, h);
+        placement.offset = offset;
+        placement.size = (width.max(1), height.max(1));
+        placement.columns = (offset.0 + width).div_ceil(cell_width).max(1) as usize;
+        placement.rows = (offset.1 + height).div_ceil(cell_height).max(1) as usize;
+        let (rows, columns) = (placement.rows, placement.columns);
+        store.place(placement, context.memory_limit);
+
+        let mut outcome = respond(&control, Ok(()));
+        if !control.stay {
+            outcome.cursor_advance = Some((rows, columns));
+        }
+        outcome
+    }
+
+    // Lowercase selectors only remove placements, uppercase ones also free the image data
+    fn delete(&mut self, control: &Control, store: &mut ImageStore, context: &Context) {
+        let free = control.delete.is_ascii_uppercase();
+        let covers_line = |p: &ImagePlacement, line: u64| p.line <= line && line < p.line + p.rows as u64;
+        let covers_column = |p: &ImagePlacement, column: usize| p.column <= column && column < p.column + p.columns;
+        let screen_line = |row: u32| context.first_visible_line + row.saturating_sub(1) as u64;
+
+        match control.delete.to_ascii_lowercase() {
+            'a' => store.delete_where(free, |p| p.line + p.rows as u64 > context.first_visible_line),
+            'i' if control.placement_id != 0 => {
+                store.delete_where(free, |p| p.image_id == control.image_id && p.placement_id == control.placement_id);
+            }
+            'i' => store.delete_image(control.image_id, free),
+            'n' => {
+                if let Some(&image_id) = self.image_numbers.get(&control.image_number) {
+                    store.delete_image(image_id, free);
+                }
+            }
+            'c' => store.delete_where(free, |p| {
+                covers_line(p, context.cursor_line) && covers_column(p, context.cursor_column)
+            }),
+            'p' => {
+                let (column, line) = (control.source.0.saturating_sub(1) as usize, screen_line(control.source.1));
+                store.delete_where(free, |p| covers_line(p, line) && covers_column(p, column));
+            }
+            'x' => {
+                let column = control.source.0.saturating_sub(1) as usize;
+                store.delete_where(free, |p| covers_column(p, column));
+            }
+            'y' => {
+                let line = screen_line(control.source.1);
+                store.delete_where(free, |p| covers_line(p, line));
+            }
+            'z' => store.delete_where(free, |p| p.z_index == control.z_index),
+            _ => {}
+        }
+    }
+}
+
+// Builds the response, honoring the quiet level and only answering commands that named an image
+fn respond(control: &Control, result: Result<(), String>) -> Outcome {
+    if control.image_id == 0 && control.image_number == 0 {
+        return Outcome::default();
+    }
+    let message = match result {
+        Ok(()) if control.quiet >= 1 => return Outcome::default(),
+        Err(_) if control.quiet >= 2 => return Outcome::default(),
+        Ok(()) => "OK".to_string(),
+        Err(e) => e,
+    };
+
+    let mut keys = Vec::new();
+    if control.image_id != 0 {
+        keys.push(format!("i={}", control.image_id));
+    }
+    if control.image_number != 0 {
+        keys.push(format!("I={}", control.image_number));
+    }
+    if control.placement_id != 0 {
+        keys.push(format!("p={}", control.placement_id));
+    }
+    Outcome {
+        reply: Some(format!("\x1b_G{};{}\x1b\\", keys.join(","), message)),
+        cursor_advance: None,
+    }
+}
+
+// Resolves the transmission medium to the raw image bytes
+fn load_data(control: &Control, payload: &[u8], max_bytes: usize) -> Result<Vec<u8>, String> {
+    if control.compression.is_some() {
+        return Err("EINVAL:compression is not supported".to_string());
+    }
+    let payload = STANDARD.decode(payload).map_err(|_| "EINVAL:invalid base64 payload".to_string())?;
+    if control.medium == 'd' {
+        return Ok(payload);
+    }
+
+    let name = String::from_utf8(payload).map_err(|_| "EINVAL:invalid path".to_string())?;
+    let path = match control.medium {
+        'f' | 't' => Path::new(&name).to_path_buf(),
+        // POSIX shared memory objects live under /dev/shm on Linux, and a name must not leave it
+        's' => {
+            let object = name.strip_prefix('/').unwrap_or(&name);
+            if object.is_empty() || object.contains('/') || object == "." || object == ".." {
+                return Err("EINVAL:invalid shared memory name".to_string());
+            }
+            Path::new(SHM_DIRECTORY).join(object)
+        }
+        _ => return Err("EINVAL:unsupported transmission medium".to_string()),
+    };
+    // Resolved so symlinks can't point the checks below (and the unlink) somewhere else
+    let path = path.canonicalize().map_err(|e| format!("EBADF:{}", e))?;
+    if control.medium == 's' && !path.starts_with(SHM_DIRECTORY) {
+        return Err("EINVAL:invalid shared memory name".to_string());
+    }
+
+    let metadata = fs::metadata(&path).map_err(|e| format!("EBADF:{}", e))?;
+    if !metadata.is_file() {
+        return Err("EINVAL:not a regular file".to_string());
+    }
+    let data = read_range(&path, metadata.len(), control, max_bytes);
+
+    // Temporary files and shared memory are consumed by the terminal, but only obvious temp files are removed
+    let is_temp_file = path.starts_with(std::env::temp_dir()) && name.contains("tty-graphics-protocol");
+    if (control.medium == 't' && is_temp_file) || control.medium == 's' {
+        let _ = fs::remove_file(&path);
+    }
+    data
+}
+
+// Reads only the requested slice of the file, refusing it before reading when it is over the limit
+fn read_range(path: &Path, len: u64, control: &Control, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let start = control.data_offset.min(len);
+    let end = if control.data_size == 0 { len } else { start.saturating_add(control.data_size as u64).min(len) };
+    if end - start > max_bytes as u64 {
+        return Err("EFBIG:image data too large".to_string());
+    }
+    let mut file = File::open(path).map_err(|e| format!("EBADF:{}", e))?;
+    file.seek(SeekFrom::Start(start)).map_err(|e| format!("EBADF:{}", e))?;
+    let mut data = Vec::with_capacity((end - start) as usize);
+    file.take(end - start).read_to_end(&mut data).map_err(|e| format!("EBADF:{}", e))?;
+    Ok(data)
+}
+
+fn decode(control: &Control, data: &[u8], max_bytes: usize) -> Result<ImageData, String> {
+    let channels = match control.format {
+        100 => return ImageData::decode(data, max_bytes).map_err(|e| format!("EBADPNG:{}", e)),
+        24 => 3,
+        32 => 4,
+        _ => return Err("EINVAL:unsupported format".to_string()),
+    };
+
+    let (width, height) = (control.data_width, control.data_height);
+    // The dimensions come from the program, so the size is checked before anything is allocated
+    let pixel_count = (width as usize)
+        .checked_mul(height as usize)
+        .filter(|&count| count.checked_mul(4).is_some_and(|bytes| bytes <= max_bytes))
+        .ok_or_else(|| "EINVAL:image dimensions too large".to_string())?;
+    let expected = pixel_count * channels;
+    if width == 0 || height == 0 || data.len() < expected {
+        return Err("ENODATA:insufficient image data".to_string());
+    }
+    let pixels = if channels == 4 {
+        data[..expected].to_vec()
+    } else {
+        data[..expected].chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect()
+    };
+    Ok(ImageData { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_dimensions_over_the_limit() {
+        let huge = parse_control("s=2147483648,v=2147483648,f=32");
+        assert_eq!(decode(&huge, &[], 1024).err().as_deref(), Some("EINVAL:image dimensions too large"));
+        let small = parse_control("s=2,v=1,f=24");
+        let image = decode(&small, &[1, 2, 3, 4, 5, 6], 1024).unwrap();
+        assert_eq!(image.pixels, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+        assert!(decode(&small, &[1, 2, 3, 4, 5, 6], 4).is_err());
+    }
+}
@@ -1,18 +1,50 @@
+mod bell;
+mod cell;
+mod charset;
+mod clipboard;
+mod cursor;
+mod export;
+mod graphics;
+mod hints;
+mod hyperlink;
+mod ipc;
+mod iterm2;
+mod kitty;
+mod mouse;
+mod palette;
+mod reports;
+mod scrollback;
 mod sdlwin;
+mod search;
 mod shaders;
+mod sixel;
+mod smart_select;
+mod termcap;
 mod terminal;
 mod terminal_state;
 mod terminal_renderer;
+mod title;
+mod vi_mode;
 
+use bell::AudibleBell;
+use clipboard::{ClipboardPolicy, ClipboardRequest, ClipboardTarget};
+use export::ExportScope;
+use hints::{Hint, HintAction};
+use ipc::{IpcRequest, IpcServer};
+use iterm2::FileTransfer;
+use mouse::{MouseEventKind, MouseModifiers, MouseReport};
 use terminal::Terminal;
-use terminal_state::TerminalState;
+use terminal_state::{SelectionKind, TerminalState, TerminalViewport};
+use vi_mode::ViMotion;
 use terminal_renderer::TerminalRenderer;
 use shaders::*;
 use sdlwin::Sdlwin;
 
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
+use sdl2::messagebox::{ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag};
 use sdl2::mouse::MouseButton;
+use sdl2::video::FlashOperation;
 use std::rc::Rc;
 use std::time::Instant;
 
@@ -39,10 +71,27 @@ fn handle_keyboard_input(
                 let _ = video_subsystem.clipboard().set_clipboard_text(&selected_text);
             }
         }
+        (Keycode::L, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) && mod_combination.contains(Mod::LSHIFTMOD) => {
+            let links = terminal_state.get_selected_links();
+            if !links.is_empty() {
+                let _ = video_subsystem.clipboard().set_clipboard_text(&links.join("\n"));
+            }
+        }
+        (Keycode::F, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) && mod_combination.contains(Mod::LSHIFTMOD) => {
+            terminal_state.start_search();
+        }
+        (Keycode::Space, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) && mod_combination.contains(Mod::LSHIFTMOD) => {
+            terminal_state.toggle_vi_mode();
+        }
+        (Keycode::H, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) && mod_combination.contains(Mod::LSHIFTMOD) => {
+            terminal_state.start_hints();
+        }
+        (Keycode::E, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) && mod_combination.contains(Mod::LSHIFTMOD) => {
+            export_to_file(terminal_state);
+        }
         (Keycode::V, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) => {
             if let Ok(text) = video_subsystem.clipboard().clipboard_text() {
-                terminal_state.add_input(&text);
-                let _ = terminal.write_input(text.as_bytes());
+                let _ = terminal.write_input(&terminal_state.paste(&text));
             }
         }
         (Keycode::C, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) => {
@@ -52,7 +101,7 @@ fn handle_keyboard_input(
             let _ = terminal.write_input(&[4]); // EOT
         }
         (Keycode::L, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) => {
-            terminal_state.clear();
+            terminal_state.erase_screen();
             let _ = terminal.write_input(b"\x0C");
         }
         (Keycode::Up, mod_combination) if mod_combination.contains(Mod::LCTRLMOD) => {
@@ -71,35 +120,132 @@ fn handle_keyboard_input(
     }
 }
 
+// Keys while the search bar is open; typed text arrives separately through TextInput
+fn handle_search_input(keycode: Keycode, keymod: Mod, terminal_state: &mut TerminalState) {
+    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+    match keycode {
+        Keycode::Escape => terminal_state.close_search(),
+        // Enter and Up walk towards older output, Shift+Enter and Down back towards the newest
+        Keycode::Return | Keycode::KpEnter if terminal_state.vi_mode_active() => terminal_state.vi_search_confirm(),
+        Keycode::Return | Keycode::KpEnter => terminal_state.search_step(!shift),
+        Keycode::Up => terminal_state.search_step(true),
+        Keycode::Down => terminal_state.search_step(false),
+        Keycode::Backspace => terminal_state.search_backspace(),
+        Keycode::Tab => terminal_state.cycle_search_mode(),
+        _ => {}
+    }
+}
+
+fn mouse_modifiers(keymod: Mod) -> MouseModifiers {
+    MouseModifiers {
+        shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+    }
+}
+
+fn mouse_report(event: &Event, viewport: &TerminalViewport, modifiers: MouseModifiers) -> Option<MouseReport> {
+    let report_button = |button: MouseButton| match button {
+        MouseButton::Left => Some(mouse::MouseButton::Left),
+        MouseButton::Middle => Some(mouse::MouseButton::Middle),
+        MouseButton::Right => Some(mouse::MouseButton::Right),
+        _ => None,
+    };
+
+    let (button, kind, x, y) = match *event {
+        Event::MouseButtonDown { mouse_btn, x, y, .. } => (report_button(mouse_btn)?, MouseEventKind::Press, x, y),
+        Event::MouseButtonUp { mouse_btn, x, y, .. } => (report_button(mouse_btn)?, MouseEventKind::Release, x, y),
+        Event::MouseMotion { x, y, .. } => (mouse::MouseButton::None, MouseEventKind::Motion, x, y),
+        Event::MouseWheel { x: dx, y: dy, mouse_x, mouse_y, .. } => {
+            let button = match (dx.signum(), dy.signum()) {
+                (_, 1) => mouse::MouseButton::WheelUp,
+                (_, -1) => mouse::MouseButton::WheelDown,
+                (1, _) => mouse::MouseButton::WheelRight,
+                (-1, _) => mouse::MouseButton::WheelLeft,
+                _ => return None,
+            };
+            (button, MouseEventKind::Press, mouse_x, mouse_y)
+        }
+        _ => return None,
+    };
+
+    let (line, column) = viewport.cell_at(x, y);
+    Some(MouseReport { button, kind, line, column, x, y, modifiers })
+}
+
 fn handle_mouse_input(
     event: &Event,
+    keymod: Mod,
     terminal_state: &mut TerminalState,
     terminal: &mut Terminal,
     video_subsystem: &sdl2::VideoSubsystem,
-    line_height: u32,
 ) {
+    let modifiers = mouse_modifiers(keymod);
+
+    if let Event::MouseMotion { x, y, .. } = event {
+        let cell = terminal_state.get_viewport().cell_at(*x, *y);
+        terminal_state.set_hovered_cell(Some(cell));
+    }
+
+    // Ctrl+click opens hyperlinks, taking precedence over mouse reporting
+    if let Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } = event {
+        if modifiers.ctrl {
+            let (line, column) = terminal_state.get_viewport().cell_at(*x, *y);
+            if let Some(link) = terminal_state.hyperlink_at(line, column) {
+                if let Err(e) = hyperlink::open_uri(&terminal_state.get_settings().link_opener, &link.uri) {
+                    eprintln!("Link error: {}", e);
+                }
+                return;
+            }
+        }
+    }
+
+    // Applications that enabled mouse tracking get the events, unless Shift forces local selection
+    if terminal_state.mouse_reporting_active() && !modifiers.shift {
+        if let Some(report) = mouse_report(event, terminal_state.get_viewport(), modifiers) {
+            if let Some(bytes) = terminal_state.encode_mouse_report(&report) {
+                let _ = terminal.write_raw(&bytes);
+            }
+        }
+        return;
+    }
+
     match event {
         Event::MouseButtonDown {
             mouse_btn: MouseButton::Left,
+            clicks,
             x,
             y,
             ..
         } => {
-            let line = *y as usize / line_height as usize;
-            let col = *x as usize / (FONT_SIZE / 2) as usize;
-            terminal_state.start_selection(line, col);
-        }
-        Event::MouseMotion { x, y, mousestate, .. } => {
-            if mousestate.left() {
-                let line = *y as usize / line_height as usize;
-                let col = *x as usize / (FONT_SIZE / 2) as usize;
-                terminal_state.update_selection(line, col);
+            let (line, col) = terminal_state.get_viewport().cell_at(*x, *y);
+            // Shift already means "select locally" while the application tracks the mouse
+            if modifiers.shift && !terminal_state.mouse_reporting_active() {
+                terminal_state.extend_selection(line, col);
+            } else {
+                terminal_state.start_selection(line, col, *clicks, modifiers.alt);
             }
         }
+        Event::MouseMotion { x, y, mousestate, .. } if mousestate.left() => {
+            terminal_state.drag_selection(*x, *y);
+        }
         Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+            terminal_state.end_selection_drag();
             let selected_text = terminal_state.get_selected_text();
-            if !selected_text.is_empty() {
-                let _ = video_subsystem.clipboard().set_clipboard_text(&selected_text);
+            let settings = terminal_state.get_settings();
+            if settings.copy_on_select && !selected_text.is_empty() {
+                let clipboard = video_subsystem.clipboard();
+                for target in &settings.copy_on_select_targets {
+                    let _ = match target {
+                        ClipboardTarget::Clipboard => clipboard.set_clipboard_text(&selected_text),
+                        ClipboardTarget::Primary => clipboard.set_primary_selection_text(&selected_text),
+                    };
+                }
+            }
+        }
+        Event::MouseButtonDown { mouse_btn: MouseButton::Middle, .. } => {
+            if let Ok(text) = video_subsystem.clipboard().primary_selection_text() {
+                let _ = terminal.write_input(&terminal_state.paste(&text));
             }
         }
         Event::MouseWheel { y, .. } => {
@@ -109,35 +255,210 @@ fn handle_mouse_input(
                 terminal_state.scroll_down(3);
             }
         }
-        Event::TextInput { text, .. } => {
-            if text.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace()) {
-                terminal_state.add_input(&text);
-                let _ = terminal.write_input(text.as_bytes());
+        Event::TextInput { text, .. } if text.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace()) => {
+            terminal_state.add_input(text);
+            let _ = terminal.write_input(text.as_bytes());
+        }
+        _ => {}
+    }
+}
+
+// Resolves a clipboard policy, asking the user when configured to; "Always" sticks for the session
+fn confirm_clipboard_access(policy: &mut ClipboardPolicy, action: &str, window: &sdl2::video::Window) -> bool {
+    match *policy {
+        ClipboardPolicy::Allow => true,
+        ClipboardPolicy::Deny => false,
+        ClipboardPolicy::Ask => {
+            let buttons = [
+                ButtonData { flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT, button_id: 0, text: "Deny" },
+                ButtonData { flags: MessageBoxButtonFlag::NOTHING, button_id: 1, text: "Allow once" },
+                ButtonData { flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT, button_id: 2, text: "Always allow" },
+            ];
+            let message = format!("An application wants to {} the clipboard.", action);
+            match sdl2::messagebox::show_message_box(MessageBoxFlag::WARNING, &buttons, "Clipboard access", &message, window, None) {
+                Ok(ClickedButton::CustomButton(button)) if button.button_id == 1 => true,
+                Ok(ClickedButton::CustomButton(button)) if button.button_id == 2 => {
+                    *policy = ClipboardPolicy::Allow;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+// Keys in vi copy mode that don't arrive as text; commands like hjkl come through TextInput
+fn handle_vi_input(keycode: Keycode, keymod: Mod, terminal_state: &mut TerminalState) {
+    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+    match keycode {
+        Keycode::Space if ctrl && shift => terminal_state.toggle_vi_mode(),
+        Keycode::Escape => terminal_state.vi_escape(),
+        Keycode::V if ctrl => terminal_state.vi_select(SelectionKind::Block),
+        Keycode::Left => terminal_state.vi_motion(ViMotion::Left),
+        Keycode::Right => terminal_state.vi_motion(ViMotion::Right),
+        Keycode::Up => terminal_state.vi_motion(ViMotion::Up),
+        Keycode::Down => terminal_state.vi_motion(ViMotion::Down),
+        Keycode::Home => terminal_state.vi_motion(ViMotion::LineStart),
+        Keycode::End => terminal_state.vi_motion(ViMotion::LineEnd),
+        _ => {}
+    }
+}
+
+// Runs the action of the hint whose label was typed
+fn run_hint(hint: Hint, terminal_state: &mut TerminalState, terminal: &mut Terminal, video_subsystem: &sdl2::VideoSubsystem) {
+    match hint.action {
+        HintAction::Copy => {
+            let _ = video_subsystem.clipboard().set_clipboard_text(&hint.text);
+        }
+        HintAction::Open => {
+            if let Err(e) = hyperlink::open_uri(&terminal_state.get_settings().link_opener, &hint.text) {
+                eprintln!("Hint error: {}", e);
+            }
+        }
+        HintAction::Paste => {
+            let _ = terminal.write_input(&terminal_state.paste(&hint.text));
+        }
+        // The editor runs in the shell, like a command typed at the prompt
+        HintAction::Edit => {
+            let command = hints::editor_command(&terminal_state.get_settings().editor, &hint.text);
+            let _ = terminal.write_input(&terminal_state.paste(&command));
+            terminal_state.commit_input();
+            let _ = terminal.write_input(b"\n");
+        }
+    }
+}
+
+// Saves the selection, or the screen when nothing is selected, in the configured export format and directory
+fn export_to_file(terminal_state: &TerminalState) {
+    let scope = if terminal_state.get_selected_text().is_empty() { ExportScope::Screen } else { ExportScope::Selection };
+    let settings = terminal_state.get_settings();
+    let contents = terminal_state.export(settings.export_format, scope);
+    match export::save(&settings.export_directory, settings.export_format, &contents) {
+        Ok(path) => println!("Exported to {}", path.display()),
+        Err(e) => eprintln!("Failed to export: {}", e),
+    }
+}
+
+fn handle_ipc_request(request: IpcRequest, terminal_state: &TerminalState) -> Result<String, String> {
+    match request {
+        IpcRequest::Export { format, scope } => {
+            if scope == ExportScope::Selection && terminal_state.get_selected_text().is_empty() {
+                return Err("nothing is selected".to_string());
+            }
+            Ok(terminal_state.export(format, scope))
+        }
+        IpcRequest::Status => Ok(format!(
+            "scrollback_lines {}\nscrollback_bytes {}\n",
+            terminal_state.scrollback_len(),
+            terminal_state.scrollback_memory_usage()
+        )),
+    }
+}
+
+// Asks before saving a file an application sent, since it lands on disk outside the terminal
+fn handle_download(transfer: FileTransfer, terminal_state: &TerminalState, window: &sdl2::video::Window) {
+    let directory = &terminal_state.get_settings().download_directory;
+    let buttons = [
+        ButtonData { flags: MessageBoxButtonFlag::ESCAPEKEY_DEFAULT, button_id: 0, text: "Discard" },
+        ButtonData { flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT, button_id: 1, text: "Save" },
+    ];
+    let message = format!(
+        "An application wants to download \"{}\" ({} bytes) to {}.",
+        transfer.file_name(),
+        transfer.data.len(),
+        directory.display()
+    );
+    match sdl2::messagebox::show_message_box(MessageBoxFlag::INFORMATION, &buttons, "Download", &message, window, None) {
+        Ok(ClickedButton::CustomButton(button)) if button.button_id == 1 => {
+            match iterm2::save_download(directory, &transfer) {
+                Ok(path) => println!("Saved download to {}", path.display()),
+                Err(e) => eprintln!("Failed to save download: {}", e),
             }
         }
         _ => {}
     }
 }
 
+fn handle_clipboard_request(
+    request: ClipboardRequest,
+    terminal_state: &mut TerminalState,
+    terminal: &mut Terminal,
+    sdlwin: &Sdlwin,
+) {
+    let clipboard = sdlwin.video_subsystem.clipboard();
+    match request {
+        ClipboardRequest::Store { target, text } => {
+            if !confirm_clipboard_access(&mut terminal_state.get_settings_mut().clipboard_write, "write to", &sdlwin.window) {
+                return;
+            }
+            let _ = match target {
+                ClipboardTarget::Clipboard => clipboard.set_clipboard_text(&text),
+                ClipboardTarget::Primary => clipboard.set_primary_selection_text(&text),
+            };
+        }
+        ClipboardRequest::Load { target, selector, terminator } => {
+            if !confirm_clipboard_access(&mut terminal_state.get_settings_mut().clipboard_read, "read", &sdlwin.window) {
+                return;
+            }
+            let text = match target {
+                ClipboardTarget::Clipboard => clipboard.clipboard_text(),
+                ClipboardTarget::Primary => clipboard.primary_selection_text(),
+            }
+            .unwrap_or_default();
+            let max_bytes = terminal_state.get_settings().clipboard_max_bytes;
+            let _ = terminal.write_raw(&clipboard::encode_reply(&selector, &text, max_bytes, terminator));
+        }
+    }
+}
+
 fn main() {
+    // `mikoshi export` and `mikoshi status` are clients for an already running terminal
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = ipc::run_cli(&args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let width: u32 = 1000;
     let height: u32 = 800;
 
-    let sdlwin = Sdlwin::new(width, height).unwrap();
+    let mut sdlwin = Sdlwin::new(width, height).unwrap();
     let video_subsystem = &sdlwin.video_subsystem;
     let ttf_context = sdl2::ttf::init().unwrap();
     let font = Rc::new(ttf_context.load_font("/usr/share/fonts/TTF/DejaVuSansMono.ttf", FONT_SIZE).unwrap());
 
     let line_height = font.height() as u32;
+    let cell_width = font.size_of_char('M').map(|(w, _)| w).unwrap_or(FONT_SIZE as u32 / 2);
 
+    // Bound before the shell starts so it inherits the socket path
+    let ipc_server = IpcServer::bind().map_err(|e| eprintln!("IPC: {}", e)).ok();
     let mut terminal = Terminal::new();
-    let mut terminal_state = TerminalState::new(width, height, line_height);
+    let mut terminal_state = TerminalState::new(width, height, line_height, cell_width);
     let mut renderer = TerminalRenderer::new(width as usize, height as usize, Rc::clone(&font));
 
     let shader_program = ShaderProgram::new("shaders/terminal.vert", "shaders/terminal.frag")
         .expect("Failed to create shader program");
     let quad = create_screen_quad();
 
+    let mut window_title = String::new();
+
+    // The audible bell is optional; without an audio device the other bell effects still work
+    let audible_bell = if terminal_state.get_settings().audible_bell {
+        let sound = terminal_state.get_settings().bell_sound.clone();
+        sdlwin.sdl.audio()
+            .and_then(|audio| AudibleBell::new(&audio, sound.as_deref()))
+            .map_err(|e| eprintln!("Bell: audio unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    // Far enough in the past that the shader sees no bell until the first one rings
+    let mut bell_time = -1000.0_f32;
+
     let start_time = Instant::now();
     let mut event_pump = sdlwin.sdl.event_pump().unwrap();
     video_subsystem.text_input().start();
@@ -157,7 +478,45 @@ fn main() {
                     keycode: Some(keycode),
                     keymod,
                     ..
-                } => handle_keyboard_input(keycode, keymod, &mut terminal_state, &mut terminal, video_subsystem),
+                } => {
+                    if terminal_state.hints_active() {
+                        match keycode {
+                            Keycode::Escape => terminal_state.cancel_hints(),
+                            Keycode::Backspace => terminal_state.hint_backspace(),
+                            _ => {}
+                        }
+                    } else if terminal_state.search_active() {
+                        handle_search_input(keycode, keymod, &mut terminal_state);
+                    } else if terminal_state.vi_mode_active() {
+                        handle_vi_input(keycode, keymod, &mut terminal_state);
+                    } else {
+                        handle_keyboard_input(keycode, keymod, &mut terminal_state, &mut terminal, video_subsystem);
+                    }
+                }
+
+                Event::TextInput { text, .. } if terminal_state.hints_active() => {
+                    if let Some(hint) = terminal_state.hint_input(&text) {
+                        run_hint(hint, &mut terminal_state, &mut terminal, video_subsystem);
+                    }
+                }
+
+                Event::TextInput { text, .. } if terminal_state.search_active() => {
+                    terminal_state.search_input(&text);
+                }
+
+                // y needs the clipboard; every other vi command is handled by the terminal state
+                Event::TextInput { text, .. } if terminal_state.vi_mode_active() => {
+                    for command in text.split_inclusive(|_| true) {
+                        if command == "y" {
+                            let yanked = terminal_state.vi_yank();
+                            if !yanked.is_empty() {
+                                let _ = video_subsystem.clipboard().set_clipboard_text(&yanked);
+                            }
+                        } else {
+                            terminal_state.vi_input(command);
+                        }
+                    }
+                }
 
                 Event::TextInput { text, .. } => {
                     terminal_state.add_input(&text);
@@ -168,7 +527,26 @@ fn main() {
                 | Event::MouseMotion { .. }
                 | Event::MouseButtonUp { .. }
                 | Event::MouseWheel { .. } => {
-                    handle_mouse_input(&event, &mut terminal_state, &mut terminal, video_subsystem, line_height);
+                    let keymod = sdlwin.sdl.keyboard().mod_state();
+                    handle_mouse_input(&event, keymod, &mut terminal_state, &mut terminal, video_subsystem);
+                }
+
+                Event::Window {
+                    win_event: sdl2::event::WindowEvent::FocusGained,
+                    ..
+                } => {
+                    if let Some(report) = terminal_state.set_focused(true) {
+                        let _ = terminal.write_raw(report);
+                    }
+                }
+
+                Event::Window {
+                    win_event: sdl2::event::WindowEvent::FocusLost,
+                    ..
+                } => {
+                    if let Some(report) = terminal_state.set_focused(false) {
+                        let _ = terminal.write_raw(report);
+                    }
                 }
 
                 Event::Window {
@@ -176,7 +554,7 @@ fn main() {
                     ..
                 } => {
                     unsafe { gl::Viewport(0, 0, w, h); }
-                    terminal_state = TerminalState::new(w as u32, h as u32, line_height);
+                    terminal_state.resize(w as u32, h as u32);
                     renderer = TerminalRenderer::new(w as usize, h as usize, Rc::clone(&font));
                 }
 
@@ -191,6 +569,46 @@ fn main() {
             }
         }
 
+        for request in terminal_state.take_clipboard_requests() {
+            handle_clipboard_request(request, &mut terminal_state, &mut terminal, &sdlwin);
+        }
+
+        for transfer in terminal_state.take_downloads() {
+            handle_download(transfer, &terminal_state, &sdlwin.window);
+        }
+
+        let replies = terminal_state.take_replies();
+        if !replies.is_empty() {
+            let _ = terminal.write_raw(&replies);
+        }
+
+        terminal_state.poll_search();
+        terminal_state.autoscroll_selection();
+
+        while let Some((request, stream)) = ipc_server.as_ref().and_then(IpcServer::poll) {
+            ipc::respond(stream, request.and_then(|request| handle_ipc_request(request, &terminal_state)));
+        }
+
+        if terminal_state.take_bell() {
+            bell_time = current_time;
+            if let Some(bell) = &audible_bell {
+                bell.ring();
+            }
+            if !terminal_state.is_focused() && terminal_state.get_settings().bell_urgency {
+                let _ = sdlwin.window.flash(FlashOperation::UntilFocused);
+            }
+        }
+
+        if terminal_state.take_palette_changed() {
+            renderer.invalidate();
+        }
+
+        let title = terminal_state.window_title();
+        if title != window_title {
+            let _ = sdlwin.window.set_title(&title);
+            window_title = title;
+        }
+
         if let Err(e) = renderer.render(&terminal_state) {
             eprintln!("Render error: {}", e);
         }
@@ -200,6 +618,17 @@ fn main() {
             shader_program.set();
             shader_program.set_uniform_f32("time", current_time);
             shader_program.set_uniform_vec2("resolution", width as f32, height as f32);
+            let dimmed = !terminal_state.is_focused() && terminal_state.get_settings().dim_unfocused;
+            shader_program.set_uniform_f32("focused", if dimmed { 0.0 } else { 1.0 });
+            let viewport = terminal_state.get_viewport();
+            let (cx, cy, cw, ch) = match (terminal_state.cursor_cell(), terminal_state.cursor_shape()) {
+                (Some((line, column)), Some(_)) => viewport.cell_rect(line, column),
+                _ => (0, 0, 0, 0),
+            };
+            let (vw, vh) = (viewport.width as f32, viewport.height as f32);
+            shader_program.set_uniform_vec4("cursor_rect", cx as f32 / vw, cy as f32 / vh, cw as f32 / vw, ch as f32 / vh);
+            shader_program.set_uniform_f32("bell_time", bell_time);
+            shader_program.set_uniform_f32("bell_mode", terminal_state.get_settings().visual_bell.shader_mode());
             gl::BindTexture(gl::TEXTURE_2D, renderer.get_texture_id());
             quad.draw();
         }
@@ -0,0 +1,295 @@
+// Application mouse reporting (DECSET 9/1000/1002/1003 with 1005/1006/1015/1016 encodings)
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    X10,         // ?9    press only, no modifiers
+    Normal,      // ?1000 press and release
+    ButtonEvent, // ?1002 plus motion while a button is held
+    AnyEvent,    // ?1003 plus all motion
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    #[default]
+    Default,   // ESC [ M Cb Cx Cy
+    Utf8,      // ?1005
+    Sgr,       // ?1006
+    Urxvt,     // ?1015
+    SgrPixels, // ?1016
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press,
+    Release,
+    Motion,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseModifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MouseReport {
+    pub button: MouseButton,
+    pub kind: MouseEventKind,
+    pub line: usize,
+    pub column: usize,
+    pub x: i32,
+    pub y: i32,
+    pub modifiers: MouseModifiers,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MouseProtocol {
+    pub tracking: MouseTracking,
+    pub encoding: MouseEncoding,
+    held_button: Option<MouseButton>,
+    last_cell: Option<(usize, usize)>,
+}
+
+impl MouseButton {
+    fn code(self) -> u32 {
+        match self {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+            MouseButton::None => 3,
+            MouseButton::WheelUp => 64,
+            MouseButton::WheelDown => 65,
+            MouseButton::WheelLeft => 66,
+            MouseButton::WheelRight => 67,
+        }
+    }
+
+    fn is_wheel(self) -> bool {
+        matches!(
+            self,
+            MouseButton::WheelUp | MouseButton::WheelDown | MouseButton::WheelLeft | MouseButton::WheelRight
+        )
+    }
+}
+
+impl MouseProtocol {
+    // Applies a DECSET/DECRST private mode, returning false if the mode isn't a mouse mode
+    pub fn set_mode(&mut self, mode: u16, enabled: bool) -> bool {
+        let tracking = match mode {
+            9 => Some(MouseTracking::X10),
+            1000 => Some(MouseTracking::Normal),
+            1002 => Some(MouseTracking::ButtonEvent),
+            1003 => Some(MouseTracking::AnyEvent),
+            _ => None,
+        };
+        if let Some(tracking) = tracking {
+            if enabled {
+                self.tracking = tracking;
+            } else if self.tracking == tracking {
+                self.tracking = MouseTracking::Off;
+            }
+            self.held_button = None;
+            self.last_cell = None;
+            return true;
+        }
+
+        let encoding = match mode {
+            1005 => MouseEncoding::Utf8,
+            1006 => MouseEncoding::Sgr,
+            1015 => MouseEncoding::Urxvt,
+            1016 => MouseEncoding::SgrPixels,
+            _ => return false,
+        };
+        if enabled {
+            self.encoding = encoding;
+        } else if self.encoding == encoding {
+            self.encoding = MouseEncoding::Default;
+        }
+        true
+    }
+
+    pub fn mode_enabled(&self, mode: u16) -> Option<bool> {
+        match mode {
+            9 => Some(self.tracking == MouseTracking::X10),
+            1000 => Some(self.tracking == MouseTracking::Normal),
+            1002 => Some(self.tracking == MouseTracking::ButtonEvent),
+            1003 => Some(self.tracking == MouseTracking::AnyEvent),
+            1005 => Some(self.encoding == MouseEncoding::Utf8),
+            1006 => Some(self.encoding == MouseEncoding::Sgr),
+            1015 => Some(self.encoding == MouseEncoding::Urxvt),
+            1016 => Some(self.encoding == MouseEncoding::SgrPixels),
+            _ => None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.tracking != MouseTracking::Off
+    }
+
+    pub fn encode(&mut self, report: &MouseReport) -> Option<Vec<u8>> {
+        let mut button = report.button;
+        match report.kind {
+            MouseEventKind::Press => {
+                if !button.is_wheel() {
+                    self.held_button = Some(button);
+                }
+            }
+            MouseEventKind::Release => {
+                if button.is_wheel() || self.tracking == MouseTracking::X10 {
+                    return None;
+                }
+                self.held_button = None;
+            }
+            MouseEventKind::Motion => {
+                match self.tracking {
+                    MouseTracking::ButtonEvent if self.held_button.is_none() => return None,
+                    MouseTracking::ButtonEvent | MouseTracking::AnyEvent => {}
+                    _ => return None,
+                }
+                // Only report motion once per cell, except for pixel-precise reporting
+                let cell = (report.line, report.column);
+                if self.encoding != MouseEncoding::SgrPixels && self.last_cell == Some(cell) {
+                    return None;
+                }
+                button = self.held_button.unwrap_or(MouseButton::None);
+            }
+        }
+        self.last_cell = Some((report.line, report.column));
+
+        let mut code = button.code();
+        if report.kind == MouseEventKind::Motion {
+            code += 32;
+        }
+        if self.tracking != MouseTracking::X10 {
+            if report.modifiers.shift {
+                code += 4;
+            }
+            if report.modifiers.alt {
+                code += 8;
+            }
+            if report.modifiers.ctrl {
+                code += 16;
+            }
+        }
+
+        let column = report.column as u32 + 1;
+        let line = report.line as u32 + 1;
+        match self.encoding {
+            MouseEncoding::Sgr | MouseEncoding::SgrPixels => {
+                let (x, y) = if self.encoding == MouseEncoding::SgrPixels {
+                    (report.x.max(0) as u32 + 1, report.y.max(0) as u32 + 1)
+                } else {
+                    (column, line)
+                };
+                let final_char = if report.kind == MouseEventKind::Release { 'm' } else { 'M' };
+                Some(format!("\x1b[<{};{};{}{}", code, x, y, final_char).into_bytes())
+            }
+            MouseEncoding::Urxvt => {
+                let code = if report.kind == MouseEventKind::Release { (code & !3) | 3 } else { code };
+                Some(format!("\x1b[{};{};{}M", code + 32, column, line).into_bytes())
+            }
+            MouseEncoding::Utf8 => {
+                let code = if report.kind == MouseEventKind::Release { (code & !3) | 3 } else { code };
+                let mut bytes = b"\x1b[M".to_vec();
+                for value in [code, column, line] {
+                    let c = char::from_u32(value + 32).filter(|_| value + 32 < 0x800)?;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(bytes)
+            }
+            MouseEncoding::Default => {
+                let code = if report.kind == MouseEventKind::Release { (code & !3) | 3 } else { code };
+                // Coordinates beyond 223 can't be represented in a single byte
+                if column > 223 || line > 223 {
+                    return None;
+                }
+                Some(vec![0x1b, b'[', b'M', (code + 32) as u8, (column + 32) as u8, (line + 32) as u8])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(button: MouseButton, kind: MouseEventKind, line: usize, column: usize) -> MouseReport {
+        MouseReport { button, kind, line, column, x: 0, y: 0, modifiers: MouseModifiers::default() }
+    }
+
+    fn protocol(tracking: u16, encoding: Option<u16>) -> MouseProtocol {
+        let mut protocol = MouseProtocol::default();
+        protocol.set_mode(tracking, true);
+        if let Some(encoding) = encoding {
+            protocol.set_mode(encoding, true);
+        }
+        protocol
+    }
+
+    #[test]
+    fn sgr_reports_press_and_release_with_modifiers() {
+        let mut protocol = protocol(1000, Some(1006));
+        let mut press = report(MouseButton::Left, MouseEventKind::Press, 4, 9);
+        press.modifiers.ctrl = true;
+        assert_eq!(protocol.encode(&press).unwrap(), b"\x1b[<16;10;5M");
+        let release = report(MouseButton::Left, MouseEventKind::Release, 4, 9);
+        assert_eq!(protocol.encode(&release).unwrap(), b"\x1b[<0;10;5m");
+    }
+
+    #[test]
+    fn default_encoding_drops_coordinates_past_223() {
+        let mut protocol = protocol(1000, None);
+        let press = report(MouseButton::Right, MouseEventKind::Press, 0, 1);
+        assert_eq!(protocol.encode(&press).unwrap(), vec![0x1b, b'[', b'M', 34, 34, 33]);
+        let far = report(MouseButton::Left, MouseEventKind::Press, 0, 300);
+        assert_eq!(protocol.encode(&far), None);
+    }
+
+    #[test]
+    fn utf8_encodes_large_coordinates_as_two_bytes() {
+        let mut protocol = protocol(1000, Some(1005));
+        let press = report(MouseButton::Left, MouseEventKind::Press, 0, 299);
+        let mut expected = b"\x1b[M ".to_vec();
+        expected.extend_from_slice("\u{14c}!".as_bytes());
+        assert_eq!(protocol.encode(&press).unwrap(), expected);
+    }
+
+    #[test]
+    fn urxvt_reports_release_as_button_three() {
+        let mut protocol = protocol(1000, Some(1015));
+        protocol.encode(&report(MouseButton::Middle, MouseEventKind::Press, 1, 1));
+        let release = report(MouseButton::Middle, MouseEventKind::Release, 1, 1);
+        assert_eq!(protocol.encode(&release).unwrap(), b"\x1b[35;2;2M");
+    }
+
+    #[test]
+    fn x10_ignores_releases_and_button_event_needs_a_held_button() {
+        let mut x10 = protocol(9, None);
+        assert!(x10.encode(&report(MouseButton::Left, MouseEventKind::Press, 0, 0)).is_some());
+        assert_eq!(x10.encode(&report(MouseButton::Left, MouseEventKind::Release, 0, 0)), None);
+
+        let mut button_event = protocol(1002, Some(1006));
+        assert_eq!(button_event.encode(&report(MouseButton::None, MouseEventKind::Motion, 0, 0)), None);
+        button_event.encode(&report(MouseButton::Left, MouseEventKind::Press, 0, 0));
+        let motion = report(MouseButton::None, MouseEventKind::Motion, 0, 1);
+        assert_eq!(button_event.encode(&motion).unwrap(), b"\x1b[<32;2;1M");
+        // Motion within the same cell is reported once
+        assert_eq!(button_event.encode(&motion), None);
+    }
+}
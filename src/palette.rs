@@ -0,0 +1,108 @@
+// 256-color palette defaults and X11 color specs used by OSC 4/10/11/12/17/19
+
+use sdl2::pixels::Color;
+
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 60),
+    (0, 205, 130),
+    (230, 200, 0),
+    (40, 110, 240),
+    (190, 60, 230),
+    (0, 200, 220),
+    (200, 200, 210),
+    (90, 90, 110),
+    (255, 60, 110),
+    (0, 255, 170),
+    (255, 240, 80),
+    (90, 160, 255),
+    (240, 110, 255),
+    (80, 240, 255),
+    (255, 255, 255),
+];
+
+pub fn default_palette() -> [Color; 256] {
+    let mut palette = [Color::RGB(0, 0, 0); 256];
+    for (i, &(r, g, b)) in ANSI_COLORS.iter().enumerate() {
+        palette[i] = Color::RGB(r, g, b);
+    }
+
+    // 6x6x6 color cube
+    let level = |v: usize| if v == 0 { 0 } else { (v * 40 + 55) as u8 };
+    for i in 0..216 {
+        palette[16 + i] = Color::RGB(level(i / 36), level((i / 6) % 6), level(i % 6));
+    }
+
+    // Grayscale ramp
+    for i in 0..24 {
+        let v = (8 + i * 10) as u8;
+        palette[232 + i] = Color::RGB(v, v, v);
+    }
+    palette
+}
+
+// Parses "rgb:r/g/b" (1-4 hex digits per channel) and "#rgb" style specs
+pub fn parse_color_spec(spec: &str) -> Option<Color> {
+    let scale = |hex: &str| -> Option<u8> {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        Some((value * 255 / max) as u8)
+    };
+
+    if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut channels = rgb.split('/').map(scale);
+        let (r, g, b) = (channels.next()??, channels.next()??, channels.next()??);
+        return channels.next().is_none().then_some(Color::RGB(r, g, b));
+    }
+
+    let hex = spec.strip_prefix('#')?;
+    if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+        return None;
+    }
+    // "#rgb" forms carry the most significant bits, unlike the scaled "rgb:" forms
+    let width = hex.len() / 3;
+    let channel = |i: usize| -> Option<u8> {
+        let digits = hex.get(i * width..(i + 1) * width)?;
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        Some(if width == 1 { (value << 4) as u8 } else { (value >> (4 * (width - 2))) as u8 })
+    };
+    Some(Color::RGB(channel(0)?, channel(1)?, channel(2)?))
+}
+
+pub fn format_color_spec(color: Color) -> String {
+    let wide = |v: u8| v as u16 * 0x101;
+    format!("rgb:{:04x}/{:04x}/{:04x}", wide(color.r), wide(color.g), wide(color.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_rgb_specs_by_channel_width() {
+        assert_eq!(parse_color_spec("rgb:f/8/0"), Some(Color::RGB(255, 136, 0)));
+        assert_eq!(parse_color_spec("rgb:ffff/8000/0000"), Some(Color::RGB(255, 127, 0)));
+        assert_eq!(parse_color_spec("rgb:ff/00"), None);
+        assert_eq!(parse_color_spec("rgb:ff/00/00/00"), None);
+        assert_eq!(parse_color_spec("rgb:12345/0/0"), None);
+    }
+
+    #[test]
+    fn hash_specs_keep_the_high_bits() {
+        assert_eq!(parse_color_spec("#f80"), Some(Color::RGB(0xf0, 0x80, 0x00)));
+        assert_eq!(parse_color_spec("#ff8800"), Some(Color::RGB(0xff, 0x88, 0x00)));
+        assert_eq!(parse_color_spec("#fff888000"), Some(Color::RGB(0xff, 0x88, 0x00)));
+        assert_eq!(parse_color_spec("#ff88"), None);
+        assert_eq!(parse_color_spec("red"), None);
+    }
+
+    #[test]
+    fn formatted_specs_parse_back() {
+        let color = Color::RGB(0x12, 0xab, 0xff);
+        assert_eq!(format_color_spec(color), "rgb:1212/abab/ffff");
+        assert_eq!(parse_color_spec(&format_color_spec(color)), Some(color));
+    }
+}
@@ -0,0 +1,50 @@
+// Device status and identification replies (DSR, CPR, DA1/DA2/DA3, XTVERSION, DECRQM)
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// DA1: a VT220-class terminal with sixel graphics and ANSI color
+pub const PRIMARY_ATTRIBUTES: &str = "\x1b[?62;4;22c";
+
+// DA3: unit id, all zeroes since we have no hardware serial number
+pub const TERTIARY_ATTRIBUTES: &str = "\x1bP!|00000000\x1b\\";
+
+pub const STATUS_OK: &str = "\x1b[0n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeStatus {
+    NotRecognized = 0,
+    Set = 1,
+    Reset = 2,
+    PermanentlyReset = 4,
+}
+
+impl From<bool> for ModeStatus {
+    fn from(enabled: bool) -> Self {
+        if enabled { ModeStatus::Set } else { ModeStatus::Reset }
+    }
+}
+
+// DA2: "1" (VT220) followed by the version as a single number, e.g. 0.1.0 -> 100
+pub fn secondary_attributes() -> String {
+    let mut parts = VERSION.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    let version = parts.next().unwrap_or(0) * 10000 + parts.next().unwrap_or(0) * 100 + parts.next().unwrap_or(0);
+    format!("\x1b[>1;{};0c", version)
+}
+
+pub fn xtversion() -> String {
+    format!("\x1bP>|Mikoshi({})\x1b\\", VERSION)
+}
+
+// CPR, with DECXCPR adding the "?" marker and the page number
+pub fn cursor_position(line: usize, column: usize, extended: bool) -> String {
+    if extended {
+        format!("\x1b[?{};{};1R", line, column)
+    } else {
+        format!("\x1b[{};{}R", line, column)
+    }
+}
+
+pub fn mode_report(mode: u16, private: bool, status: ModeStatus) -> String {
+    let marker = if private { "?" } else { "" };
+    format!("\x1b[{}{};{}$y", marker, mode, status as u8)
+}
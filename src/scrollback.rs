@@ -0,0 +1,642 @@
+// Scrollback storage: compact lines in fixed-size pages, with older pages LZ4-compressed
+// and, optionally, pages evicted from memory appended to a file on disk
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cell::{Cell, CellColor, Line, Style};
+use super::hyperlink::HyperlinkId;
+
+const PAGE_LINES: usize = 256;
+// The newest pages stay uncompressed since they are the ones being scrolled through most
+const HOT_PAGES: usize = 4;
+// Decoded cold pages kept around, enough for a viewport straddling page boundaries
+const CACHED_PAGES: usize = 4;
+
+// A line as stored: the text once, plus runs of cells sharing the same attributes
+#[derive(Clone, Debug, Default)]
+pub struct CompactLine {
+    text: Box<str>,
+    ascii: bool,
+    runs: Box<[AttributeRun]>,
+    wrapped: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AttributeRun {
+    length: u32,
+    style: Style,
+    hyperlink: Option<HyperlinkId>,
+}
+
+enum PageData {
+    Hot(Vec<CompactLine>),
+    Cold { compressed: Arc<[u8]>, lines: usize },
+}
+
+struct Page {
+    id: u64,
+    data: PageData,
+    bytes: usize,
+}
+
+// Where a page spilled to disk lives in the file
+struct DiskPage {
+    id: u64,
+    offset: u64,
+    length: usize,
+    lines: usize,
+}
+
+// Append-only page file; the index stays in memory since it is a few words per 256 lines
+struct DiskStore {
+    file: File,
+    path: PathBuf,
+    pages: Vec<DiskPage>,
+    end: u64,
+    keep: bool,
+    shred: bool,
+}
+
+// A copy of the scrollback that can be read on another thread; compressed pages are shared, not copied
+pub struct ScrollbackSnapshot {
+    pages: Vec<SnapshotPage>,
+    skip: usize,
+}
+
+enum SnapshotPage {
+    Lines(Vec<(String, bool)>),
+    Compressed { data: Arc<[u8]>, lines: usize },
+    Disk { path: PathBuf, offset: u64, length: usize, lines: usize },
+}
+
+#[derive(Default)]
+pub struct Scrollback {
+    pages: VecDeque<Page>,
+    // Lines already evicted from the front page, which is only dropped once all of them are gone
+    front_skip: usize,
+    len: usize,
+    disk: Option<DiskStore>,
+    disk_lines: usize,
+    bytes: usize,
+    next_page_id: u64,
+    // The most recently read cold pages, newest first, so scrolling through them doesn't
+    // decompress (or read back from disk) on every frame
+    cache: RefCell<VecDeque<(u64, Rc<Vec<CompactLine>>)>>,
+}
+
+impl CompactLine {
+    pub fn from_line(line: &Line) -> Self {
+        let text: String = line.cells.iter().map(|cell| cell.c).collect();
+        let mut runs: Vec<AttributeRun> = Vec::new();
+        for cell in &line.cells {
+            match runs.last_mut() {
+                Some(run) if run.style == cell.style && run.hyperlink == cell.hyperlink => run.length += 1,
+                _ => runs.push(AttributeRun { length: 1, style: cell.style, hyperlink: cell.hyperlink }),
+            }
+        }
+        Self {
+            ascii: text.is_ascii(),
+            text: text.into_boxed_str(),
+            runs: runs.into_boxed_slice(),
+            wrapped: line.wrapped,
+        }
+    }
+
+    pub fn to_line(&self) -> Line {
+        let mut cells = Vec::with_capacity(self.len());
+        let mut chars = self.text.chars();
+        for run in self.runs.iter() {
+            for c in chars.by_ref().take(run.length as usize) {
+                cells.push(Cell { c, style: run.style, hyperlink: run.hyperlink });
+            }
+        }
+        Line { cells, wrapped: self.wrapped }
+    }
+
+    pub fn len(&self) -> usize {
+        if self.ascii {
+            self.text.len()
+        } else {
+            self.text.chars().count()
+        }
+    }
+
+    fn byte_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.text.len() + self.runs.len() * std::mem::size_of::<AttributeRun>()
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.wrapped as u8);
+        out.extend_from_slice(&(self.text.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.text.as_bytes());
+        out.extend_from_slice(&(self.runs.len() as u32).to_le_bytes());
+        for run in self.runs.iter() {
+            out.extend_from_slice(&run.length.to_le_bytes());
+            encode_color(run.style.fg, out);
+            encode_color(run.style.bg, out);
+            let style = run.style;
+            let flags = [style.bold, style.dim, style.italic, style.underline, style.inverse, style.hidden, style.strikethrough]
+                .iter()
+                .enumerate()
+                .fold(0u8, |flags, (bit, &set)| flags | ((set as u8) << bit));
+            out.push(flags);
+            let link = run.hyperlink.map_or(0, |id| id.0 + 1);
+            out.extend_from_slice(&link.to_le_bytes());
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let wrapped = take(input, 1)?[0] != 0;
+        let text_len = read_u32(input)? as usize;
+        let text = std::str::from_utf8(take(input, text_len)?).ok()?;
+        let run_count = read_u32(input)? as usize;
+        let mut runs = Vec::with_capacity(run_count);
+        for _ in 0..run_count {
+            let length = read_u32(input)?;
+            let fg = decode_color(input)?;
+            let bg = decode_color(input)?;
+            let flags = take(input, 1)?[0];
+            let bit = |n: u8| flags & (1 << n) != 0;
+            let style = Style {
+                fg,
+                bg,
+                bold: bit(0),
+                dim: bit(1),
+                italic: bit(2),
+                underline: bit(3),
+                inverse: bit(4),
+                hidden: bit(5),
+                strikethrough: bit(6),
+            };
+            let hyperlink = read_u32(input)?.checked_sub(1).map(HyperlinkId);
+            runs.push(AttributeRun { length, style, hyperlink });
+        }
+        Some(Self {
+            ascii: text.is_ascii(),
+            text: text.into(),
+            runs: runs.into_boxed_slice(),
+            wrapped,
+        })
+    }
+}
+
+impl Page {
+    fn len(&self) -> usize {
+        match &self.data {
+            PageData::Hot(lines) => lines.len(),
+            PageData::Cold { lines, .. } => *lines,
+        }
+    }
+
+    fn compress(&mut self) {
+        let PageData::Hot(lines) = &self.data else {
+            return;
+        };
+        if lines.is_empty() {
+            return;
+        }
+        let mut encoded = Vec::new();
+        for line in lines {
+            line.encode(&mut encoded);
+        }
+        let compressed: Arc<[u8]> = lz4_flex::compress_prepend_size(&encoded).into();
+        self.bytes = compressed.len();
+        self.data = PageData::Cold { compressed, lines: lines.len() };
+    }
+}
+
+impl DiskStore {
+    fn create(directory: &Path, keep: bool, shred: bool) -> Result<Self, String> {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let path = directory.join(format!("scrollback-{}-{}.bin", std::process::id(), stamp));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self { file, path, pages: Vec::new(), end: 0, keep, shred })
+    }
+
+    // Records are a small header (line count, length) followed by the compressed page
+    fn append(&mut self, id: u64, compressed: &[u8], lines: usize) -> io::Result<()> {
+        let mut record = Vec::with_capacity(8 + compressed.len());
+        record.extend_from_slice(&(lines as u32).to_le_bytes());
+        record.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        record.extend_from_slice(compressed);
+        self.file.write_all_at(&record, self.end)?;
+        self.pages.push(DiskPage { id, offset: self.end + 8, length: compressed.len(), lines });
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn read(&self, page: &DiskPage) -> io::Result<Vec<u8>> {
+        let mut compressed = vec![0; page.length];
+        self.file.read_exact_at(&mut compressed, page.offset)?;
+        Ok(compressed)
+    }
+
+    // Overwrites the contents, so the output doesn't linger in free blocks
+    fn shred(&mut self) {
+        let zeros = vec![0u8; 64 * 1024];
+        let mut offset = 0;
+        while offset < self.end {
+            let chunk = (self.end - offset).min(zeros.len() as u64) as usize;
+            if self.file.write_all_at(&zeros[..chunk], offset).is_err() {
+                break;
+            }
+            offset += chunk as u64;
+        }
+        let _ = self.file.sync_all();
+    }
+
+    fn truncate(&mut self) {
+        if self.shred {
+            self.shred();
+        }
+        self.pages.clear();
+        self.end = 0;
+        let _ = self.file.set_len(0);
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        if self.shred {
+            self.shred();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// $XDG_CACHE_HOME/mikoshi, falling back to ~/.cache/mikoshi
+pub fn default_cache_directory() -> PathBuf {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    cache.join("mikoshi")
+}
+
+impl Scrollback {
+    // Keeps every line, spilling pages that leave memory to a file in the directory
+    pub fn with_disk(directory: &Path, keep: bool, shred: bool) -> Result<Self, String> {
+        Ok(Self { disk: Some(DiskStore::create(directory, keep, shred)?), ..Self::default() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.disk_lines + self.len
+    }
+
+    // Approximate heap usage of the stored lines
+    pub fn memory_usage(&self) -> usize {
+        self.bytes
+    }
+
+    // Appends a line, then evicts the oldest lines past either limit, returning how many went
+    pub fn push(&mut self, line: &Line, max_lines: usize, max_bytes: usize) -> usize {
+        let line = CompactLine::from_line(line);
+        let needs_page = self.pages.back().is_none_or(|page| page.len() >= PAGE_LINES);
+        if needs_page {
+            self.pages.push_back(Page { id: self.next_page_id, data: PageData::Hot(Vec::with_capacity(PAGE_LINES)), bytes: 0 });
+            self.next_page_id += 1;
+            if let Some(page) = self.pages.len().checked_sub(HOT_PAGES + 1).and_then(|index| self.pages.get_mut(index)) {
+                let before = page.bytes;
+                page.compress();
+                self.bytes = self.bytes - before + page.bytes;
+            }
+        }
+
+        let page = self.pages.back_mut().expect("a page was just ensured");
+        let bytes = line.byte_size();
+        if let PageData::Hot(lines) = &mut page.data {
+            lines.push(line);
+        }
+        page.bytes += bytes;
+        self.bytes += bytes;
+        self.len += 1;
+
+        let over_limit = |scrollback: &Self| scrollback.len > max_lines || scrollback.bytes > max_bytes;
+        let mut evicted = 0;
+        if self.disk.is_some() {
+            // Whole pages move to disk, except the one still being filled
+            while over_limit(self) && self.pages.len() > 1 {
+                if let Err(e) = self.spill_front() {
+                    eprintln!("Scrollback: writing to disk failed, keeping lines in memory only: {}", e);
+                    // The lines already on disk go with the file, so they count as evicted
+                    self.disk = None;
+                    evicted = std::mem::take(&mut self.disk_lines);
+                    break;
+                }
+            }
+            if self.disk.is_some() {
+                return 0;
+            }
+        }
+
+        while self.len > max_lines || (self.bytes > max_bytes && self.len > 1) {
+            self.pop_front();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    fn spill_front(&mut self) -> io::Result<()> {
+        let (Some(disk), Some(mut page)) = (self.disk.as_mut(), self.pages.pop_front()) else {
+            return Ok(());
+        };
+        let bytes = page.bytes;
+        page.compress();
+        if let PageData::Cold { compressed, lines } = &page.data {
+            if let Err(e) = disk.append(page.id, compressed, *lines) {
+                // The page stays in memory, now compressed
+                self.bytes = self.bytes - bytes + page.bytes;
+                self.pages.push_front(page);
+                return Err(e);
+            }
+            self.disk_lines += lines;
+            self.len -= lines;
+        }
+        self.bytes -= bytes;
+        Ok(())
+    }
+
+    fn pop_front(&mut self) {
+        let Some(page) = self.pages.front() else {
+            return;
+        };
+        self.front_skip += 1;
+        self.len -= 1;
+        if self.front_skip >= page.len() {
+            self.bytes -= page.bytes;
+            self.pages.pop_front();
+            self.front_skip = 0;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.front_skip = 0;
+        self.len = 0;
+        self.disk_lines = 0;
+        if let Some(disk) = &mut self.disk {
+            disk.truncate();
+        }
+        self.bytes = 0;
+        self.cache.borrow_mut().clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<Line> {
+        self.range(index, index + 1).pop()
+    }
+
+    // Lines [start, end), reading back disk pages and decompressing cold pages as needed
+    pub fn range(&self, start: usize, end: usize) -> Vec<Line> {
+        let end = end.min(self.len());
+        let mut result = Vec::with_capacity(end.saturating_sub(start));
+
+        let mut page_start = 0;
+        if let Some(disk) = &self.disk {
+            for page in &disk.pages {
+                let page_end = page_start + page.lines;
+                if start < page_end && page_start < end {
+                    let lines = self.cached_lines(page.id, page.lines, || disk.read(page).unwrap_or_default());
+                    let (first, last) = (start.max(page_start), end.min(page_end));
+                    result.extend(lines[first - page_start..last - page_start].iter().map(CompactLine::to_line));
+                }
+                page_start = page_end;
+            }
+        }
+
+        // In-memory indices are shifted by the lines skipped at the front of the first page
+        let page_start = page_start as isize - self.front_skip as isize;
+        let mut page_start = page_start;
+        for page in &self.pages {
+            let page_end = page_start + page.len() as isize;
+            let (first, last) = ((start as isize).max(page_start), (end as isize).min(page_end));
+            if first < last {
+                let (first, last) = ((first - page_start) as usize, (last - page_start) as usize);
+                match &page.data {
+                    PageData::Hot(lines) => result.extend(lines[first..last].iter().map(CompactLine::to_line)),
+                    PageData::Cold { compressed, lines: count } => {
+                        let lines = self.cached_lines(page.id, *count, || compressed.to_vec());
+                        result.extend(lines[first..last].iter().map(CompactLine::to_line));
+                    }
+                }
+            }
+            page_start = page_end;
+        }
+        result
+    }
+
+    pub fn snapshot(&self) -> ScrollbackSnapshot {
+        let mut pages = Vec::new();
+        if let Some(disk) = &self.disk {
+            for page in &disk.pages {
+                pages.push(SnapshotPage::Disk {
+                    path: disk.path.clone(),
+                    offset: page.offset,
+                    length: page.length,
+                    lines: page.lines,
+                });
+            }
+        }
+        for page in &self.pages {
+            pages.push(match &page.data {
+                PageData::Hot(lines) => {
+                    SnapshotPage::Lines(lines.iter().map(|line| (line.text.to_string(), line.wrapped)).collect())
+                }
+                PageData::Cold { compressed, lines } => {
+                    SnapshotPage::Compressed { data: Arc::clone(compressed), lines: *lines }
+                }
+            });
+        }
+        ScrollbackSnapshot { pages, skip: self.front_skip }
+    }
+
+    // Decodes a compressed page, keeping the least recently used ones out of the cache
+    fn cached_lines(&self, id: u64, count: usize, compressed: impl FnOnce() -> Vec<u8>) -> Rc<Vec<CompactLine>> {
+        let mut cache = self.cache.borrow_mut();
+        if let Some(position) = cache.iter().position(|(cached_id, _)| *cached_id == id) {
+            let entry = cache.remove(position).unwrap();
+            let lines = Rc::clone(&entry.1);
+            cache.push_front(entry);
+            return lines;
+        }
+
+        let decoded = lz4_flex::decompress_size_prepended(&compressed()).unwrap_or_default();
+        let mut input = decoded.as_slice();
+        let mut lines: Vec<CompactLine> = (0..count).map_while(|_| CompactLine::decode(&mut input)).collect();
+        // A page that fails to decode still has to keep its line count
+        lines.resize_with(count, CompactLine::default);
+
+        let lines = Rc::new(lines);
+        cache.push_front((id, Rc::clone(&lines)));
+        cache.truncate(CACHED_PAGES);
+        lines
+    }
+}
+
+impl ScrollbackSnapshot {
+    // Calls back with each line's text and soft-wrap flag, oldest first
+    pub fn for_each_line(&self, mut f: impl FnMut(&str, bool)) {
+        let mut skip = self.skip;
+        let mut emit = |text: &str, wrapped: bool| {
+            if skip > 0 {
+                skip -= 1;
+            } else {
+                f(text, wrapped);
+            }
+        };
+
+        for page in &self.pages {
+            let (compressed, count) = match page {
+                SnapshotPage::Lines(lines) => {
+                    for (text, wrapped) in lines {
+                        emit(text, *wrapped);
+                    }
+                    continue;
+                }
+                SnapshotPage::Compressed { data, lines } => (data.to_vec(), *lines),
+                SnapshotPage::Disk { path, offset, length, lines } => {
+                    let mut data = vec![0; *length];
+                    let read = File::open(path).and_then(|file| file.read_exact_at(&mut data, *offset));
+                    (if read.is_ok() { data } else { Vec::new() }, *lines)
+                }
+            };
+
+            let decoded = lz4_flex::decompress_size_prepended(&compressed).unwrap_or_default();
+            let mut input = decoded.as_slice();
+            for _ in 0..count {
+                match CompactLine::decode(&mut input) {
+                    Some(line) => emit(&line.text, line.wrapped),
+                    None => emit("", false),
+                }
+            }
+        }
+    }
+}
+
+fn encode_color(color: CellColor, out: &mut Vec<u8>) {
+    out.extend_from_slice(&match color {
+        CellColor::Default => [0, 0, 0, 0],
+        CellColor::Indexed(index) => [1, index, 0, 0],
+        CellColor::Rgb(r, g, b) => [2, r, g, b],
+    });
+}
+
+fn decode_color(input: &mut &[u8]) -> Option<CellColor> {
+    let bytes = take(input, 4)?;
+    Some(match bytes[0] {
+        1 => CellColor::Indexed(bytes[1]),
+        2 => CellColor::Rgb(bytes[1], bytes[2], bytes[3]),
+        _ => CellColor::Default,
+    })
+}
+
+fn take<'a>(input: &mut &'a [u8], count: usize) -> Option<&'a [u8]> {
+    if input.len() < count {
+        return None;
+    }
+    let (head, tail) = input.split_at(count);
+    *input = tail;
+    Some(head)
+}
+
+fn read_u32(input: &mut &[u8]) -> Option<u32> {
+    take(input, 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str, wrapped: bool) -> Line {
+        let mut cells: Vec<Cell> = text.chars().map(|c| Cell { c, ..Cell::default() }).collect();
+        if let Some(cell) = cells.first_mut() {
+            cell.style.bold = true;
+            cell.style.fg = CellColor::Rgb(1, 2, 3);
+        }
+        if let Some(cell) = cells.last_mut() {
+            cell.style.bg = CellColor::Indexed(200);
+            cell.hyperlink = Some(HyperlinkId(0));
+        }
+        Line { cells, wrapped }
+    }
+
+    #[test]
+    fn compact_lines_survive_encoding() {
+        for original in [line("héllo wörld", true), line("plain", false), line("", false)] {
+            let compact = CompactLine::from_line(&original);
+            assert_eq!(compact.len(), original.len());
+            let mut encoded = Vec::new();
+            compact.encode(&mut encoded);
+            let decoded = CompactLine::decode(&mut encoded.as_slice()).unwrap();
+            assert_eq!(decoded.to_line(), original);
+        }
+    }
+
+    #[test]
+    fn truncated_records_fail_to_decode() {
+        let mut encoded = Vec::new();
+        CompactLine::from_line(&line("text", false)).encode(&mut encoded);
+        encoded.pop();
+        assert!(CompactLine::decode(&mut encoded.as_slice()).is_none());
+    }
+
+    #[test]
+    fn reads_back_lines_from_compressed_pages() {
+        let mut scrollback = Scrollback::default();
+        let count = PAGE_LINES * (HOT_PAGES + 2) + 10;
+        for n in 0..count {
+            scrollback.push(&line(&format!("line {}", n), n.is_multiple_of(3)), usize::MAX, usize::MAX);
+        }
+        assert_eq!(scrollback.len(), count);
+        // A range straddling two cold pages, read twice to go through the cache
+        for _ in 0..2 {
+            let lines = scrollback.range(PAGE_LINES - 2, PAGE_LINES + 2);
+            assert_eq!(lines, (PAGE_LINES - 2..PAGE_LINES + 2).map(|n| line(&format!("line {}", n), n.is_multiple_of(3))).collect::<Vec<_>>());
+        }
+        assert_eq!(scrollback.get(count - 1), Some(line(&format!("line {}", count - 1), (count - 1).is_multiple_of(3))));
+    }
+
+    #[test]
+    fn lines_on_a_failed_disk_count_as_evicted() {
+        let directory = std::env::temp_dir().join(format!("mikoshi-test-{}", std::process::id()));
+        let mut scrollback = Scrollback::with_disk(&directory, false, false).unwrap();
+        let text = |n: usize| format!("line {}", n);
+        let max_lines = PAGE_LINES * 2;
+        for n in 0..PAGE_LINES * 3 {
+            assert_eq!(scrollback.push(&line(&text(n), false), max_lines, usize::MAX), 0);
+        }
+        assert_eq!(scrollback.disk_lines, PAGE_LINES);
+
+        // A read-only handle makes the next spill fail
+        scrollback.disk.as_mut().unwrap().file = File::open("/dev/null").unwrap();
+        let count = scrollback.len();
+        let mut evicted = 0;
+        for n in count..count + PAGE_LINES {
+            evicted += scrollback.push(&line(&text(n), false), max_lines, usize::MAX);
+        }
+        assert!(scrollback.disk.is_none());
+        assert_eq!(scrollback.disk_lines, 0);
+        assert_eq!(evicted + scrollback.len(), count + PAGE_LINES);
+        // The first line left is the one right after the evicted ones
+        assert_eq!(scrollback.get(0), Some(line(&text(evicted), false)));
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
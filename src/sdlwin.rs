@@ -31,7 +31,7 @@ impl Sdlwin {
             .gl_set_swap_interval(SwapInterval::VSync)
             .map_err(|e| format!("Failed to set swap interval: {}", e))?;
 
-        let _gl = gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const _);
+        gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const _);
 
         Ok(Sdlwin {
             sdl,
@@ -0,0 +1,334 @@
+// Scrollback search: queries run on a worker thread against a snapshot of the history
+
+use regex::{Regex, RegexBuilder};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use super::scrollback::ScrollbackSnapshot;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchMode {
+    Plain,
+    CaseInsensitive,
+    Regex,
+}
+
+// A hit from (line, column) up to (line, column) exclusive, with lines as absolute line ids
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub start: (u64, usize),
+    pub end: (u64, usize),
+}
+
+struct SearchJob {
+    generation: u64,
+    query: String,
+    mode: SearchMode,
+    snapshot: ScrollbackSnapshot,
+    first_line: u64,
+    max_matches: usize,
+}
+
+struct SearchResult {
+    generation: u64,
+    matches: Result<Vec<SearchMatch>, String>,
+}
+
+pub struct Search {
+    pub query: String,
+    pub mode: SearchMode,
+    pub matches: Vec<SearchMatch>,
+    pub current: Option<usize>,
+    pub error: Option<String>,
+    pub truncated: bool,
+    generation: u64,
+    pending: bool,
+    max_matches: usize,
+    jobs: Sender<SearchJob>,
+    results: Receiver<SearchResult>,
+}
+
+impl SearchMode {
+    pub fn next(self) -> Self {
+        match self {
+            SearchMode::Plain => SearchMode::CaseInsensitive,
+            SearchMode::CaseInsensitive => SearchMode::Regex,
+            SearchMode::Regex => SearchMode::Plain,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchMode::Plain => "text",
+            SearchMode::CaseInsensitive => "any case",
+            SearchMode::Regex => "regex",
+        }
+    }
+
+    fn build(self, query: &str) -> Result<Regex, String> {
+        let pattern = match self {
+            SearchMode::Regex => query.to_string(),
+            _ => regex::escape(query),
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self == SearchMode::CaseInsensitive)
+            .build()
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Search {
+    pub fn new(mode: SearchMode) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<SearchJob>();
+        let (result_sender, results) = mpsc::channel();
+        // The worker exits once the search is dropped and its job channel closes
+        thread::spawn(move || {
+            while let Ok(mut job) = job_receiver.recv() {
+                // Only the newest query matters when the user types faster than we search
+                while let Ok(newer) = job_receiver.try_recv() {
+                    job = newer;
+                }
+                let matches = run(&job);
+                if result_sender.send(SearchResult { generation: job.generation, matches }).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            query: String::new(),
+            mode,
+            matches: Vec::new(),
+            current: None,
+            error: None,
+            truncated: false,
+            generation: 0,
+            pending: false,
+            max_matches: 0,
+            jobs,
+            results,
+        }
+    }
+
+    pub fn start(&mut self, snapshot: ScrollbackSnapshot, first_line: u64, max_matches: usize) {
+        self.generation += 1;
+        self.max_matches = max_matches;
+        if self.query.is_empty() {
+            self.pending = false;
+            self.matches.clear();
+            self.current = None;
+            self.error = None;
+            return;
+        }
+        let job = SearchJob {
+            generation: self.generation,
+            query: self.query.clone(),
+            mode: self.mode,
+            snapshot,
+            first_line,
+            max_matches,
+        };
+        self.pending = self.jobs.send(job).is_ok();
+    }
+
+    // Takes the result of the latest query if it is ready, returning true when the matches changed
+    pub fn poll(&mut self) -> bool {
+        let mut updated = false;
+        while let Ok(result) = self.results.try_recv() {
+            updated |= self.apply(result);
+        }
+        updated
+    }
+
+    // Blocks until the latest query has finished, returning true when the matches changed
+    pub fn wait(&mut self) -> bool {
+        let mut updated = self.poll();
+        while self.pending {
+            match self.results.recv() {
+                Ok(result) => updated |= self.apply(result),
+                Err(_) => self.pending = false,
+            }
+        }
+        updated
+    }
+
+    fn apply(&mut self, result: SearchResult) -> bool {
+        if result.generation != self.generation {
+            return false;
+        }
+        self.pending = false;
+        match result.matches {
+            Ok(matches) => {
+                self.error = None;
+                self.truncated = matches.len() >= self.max_matches;
+                self.matches = matches;
+            }
+            Err(e) => {
+                self.error = Some(e);
+                self.matches.clear();
+            }
+        }
+        true
+    }
+
+    // Moves to the next (older when backwards) match, wrapping around at either end
+    pub fn step(&mut self, backwards: bool) -> Option<SearchMatch> {
+        let count = self.matches.len();
+        if count == 0 {
+            return None;
+        }
+        let index = match (self.current, backwards) {
+            (None, _) => count - 1,
+            (Some(i), true) => (i + count - 1) % count,
+            (Some(i), false) => (i + 1) % count,
+        };
+        self.current = Some(index);
+        Some(self.matches[index])
+    }
+
+    // Prompt shown in the search bar, with the match counter
+    pub fn status(&self) -> String {
+        let counter = if let Some(error) = &self.error {
+            format!("invalid pattern: {}", error.lines().last().unwrap_or(""))
+        } else if self.pending {
+            "searching...".to_string()
+        } else if self.query.is_empty() {
+            String::new()
+        } else {
+            let more = if self.truncated { "+" } else { "" };
+            match self.current {
+                Some(index) => format!("{}/{}{}", index + 1, self.matches.len(), more),
+                None if self.matches.len() == 1 => "1 match".to_string(),
+                None => format!("{}{} matches", self.matches.len(), more),
+            }
+        };
+        format!("Find ({}): {}_   {}", self.mode.label(), self.query, counter)
+    }
+}
+
+// Searches logical lines, joining soft-wrapped rows so matches can span them
+fn run(job: &SearchJob) -> Result<Vec<SearchMatch>, String> {
+    let regex = job.mode.build(&job.query)?;
+    let mut matches = Vec::new();
+    let mut text = String::new();
+    // (line index, byte offset in `text`) of every row in the logical line
+    let mut rows: Vec<(usize, usize)> = Vec::new();
+    let mut index = 0;
+
+    let search_logical_line = |text: &str, rows: &[(usize, usize)], matches: &mut Vec<SearchMatch>| {
+        for found in regex.find_iter(text) {
+            if matches.len() >= job.max_matches {
+                break;
+            }
+            if found.is_empty() {
+                continue;
+            }
+            let locate = |offset: usize, at_end: bool| {
+                // The end of a match that finishes a row belongs to that row, not the start of the next
+                let row = rows
+                    .iter()
+                    .rposition(|&(_, start)| if at_end { start < offset } else { start <= offset })
+                    .unwrap_or(0);
+                let (line, start) = rows[row];
+                (job.first_line + line as u64, text[start..offset].chars().count())
+            };
+            matches.push(SearchMatch { start: locate(found.start(), false), end: locate(found.end(), true) });
+        }
+    };
+
+    job.snapshot.for_each_line(|line, wrapped| {
+        rows.push((index, text.len()));
+        text.push_str(line);
+        index += 1;
+        if !wrapped {
+            search_logical_line(&text, &rows, &mut matches);
+            text.clear();
+            rows.clear();
+        }
+    });
+    if !rows.is_empty() {
+        search_logical_line(&text, &rows, &mut matches);
+    }
+    Ok(matches)
+}
+
+// The first match after `point`, or the last one before it when going backwards, wrapping around at either end
+pub fn next_match(matches: &[SearchMatch], point: (u64, usize), backwards: bool) -> Option<usize> {
+    if matches.is_empty() {
+        return None;
+    }
+    if backwards {
+        let before = matches.partition_point(|m| m.start < point);
+        Some(before.checked_sub(1).unwrap_or(matches.len() - 1))
+    } else {
+        let after = matches.partition_point(|m| m.start <= point);
+        Some(if after < matches.len() { after } else { 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Cell, Line};
+    use crate::scrollback::Scrollback;
+
+    fn job(rows: &[(&str, bool)], query: &str, mode: SearchMode, max_matches: usize) -> SearchJob {
+        let mut scrollback = Scrollback::default();
+        for (text, wrapped) in rows {
+            let line = Line { cells: text.chars().map(|c| Cell { c, ..Cell::default() }).collect(), wrapped: *wrapped };
+            scrollback.push(&line, usize::MAX, usize::MAX);
+        }
+        SearchJob { generation: 0, query: query.to_string(), mode, snapshot: scrollback.snapshot(), first_line: 10, max_matches }
+    }
+
+    fn span(start: (u64, usize), end: (u64, usize)) -> SearchMatch {
+        SearchMatch { start, end }
+    }
+
+    #[test]
+    fn matches_across_soft_wrapped_rows() {
+        let rows = [("hello wö", true), ("rld", false), ("world", false)];
+        let matches = run(&job(&rows, "wörld", SearchMode::Plain, 100)).unwrap();
+        assert_eq!(matches, vec![span((10, 6), (11, 3))]);
+        let matches = run(&job(&rows, "WOrLD", SearchMode::CaseInsensitive, 100)).unwrap();
+        assert_eq!(matches, vec![span((12, 0), (12, 5))]);
+    }
+
+    #[test]
+    fn a_match_ending_a_row_stays_on_that_row() {
+        let matches = run(&job(&[("abc", true), ("def", false)], "abc", SearchMode::Plain, 100)).unwrap();
+        assert_eq!(matches, vec![span((10, 0), (10, 3))]);
+    }
+
+    #[test]
+    fn plain_queries_are_literal_and_matches_are_capped() {
+        let rows = [("a.c abc a.c", false)];
+        assert_eq!(run(&job(&rows, "a.c", SearchMode::Plain, 100)).unwrap().len(), 2);
+        assert_eq!(run(&job(&rows, "a.c", SearchMode::Regex, 100)).unwrap().len(), 3);
+        assert_eq!(run(&job(&rows, "a.c", SearchMode::Regex, 1)).unwrap().len(), 1);
+        assert!(run(&job(&rows, "a(", SearchMode::Regex, 100)).is_err());
+    }
+
+    #[test]
+    fn next_match_wraps_around() {
+        let matches = [span((1, 0), (1, 2)), span((3, 4), (3, 6))];
+        assert_eq!(next_match(&matches, (1, 0), false), Some(1));
+        assert_eq!(next_match(&matches, (3, 4), false), Some(0));
+        assert_eq!(next_match(&matches, (3, 4), true), Some(0));
+        assert_eq!(next_match(&matches, (1, 0), true), Some(1));
+        assert_eq!(next_match(&[], (0, 0), false), None);
+    }
+
+    #[test]
+    fn wait_returns_the_latest_query_only() {
+        let mut search = Search::new(SearchMode::Plain);
+        search.query = "abc".to_string();
+        search.start(job(&[("abc abc", false)], "", SearchMode::Plain, 100).snapshot, 10, 100);
+        search.query = "c a".to_string();
+        search.start(job(&[("abc abc", false)], "", SearchMode::Plain, 100).snapshot, 10, 100);
+        assert!(search.wait());
+        assert!(!search.pending);
+        assert_eq!(search.matches, vec![span((10, 2), (10, 5))]);
+        assert!(!search.wait());
+    }
+}
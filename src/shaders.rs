@@ -40,9 +40,10 @@ impl ShaderProgram {
             if success != gl::TRUE as GLint {
                 let mut len = 0;
                 gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-                let mut buffer = Vec::with_capacity(len as usize);
-                buffer.set_len((len as usize) - 1);
+                // Room for the whole log, which GL ends with a NUL
+                let mut buffer = vec![0u8; len.max(1) as usize];
                 gl::GetShaderInfoLog(shader, len, ptr::null_mut(), buffer.as_mut_ptr() as *mut GLchar);
+                buffer.truncate((len as usize).saturating_sub(1));
                 return Err(format!(
                     "Shader compilation failed: {}",
                     str::from_utf8(&buffer).unwrap_or("Unknown error")
@@ -65,9 +66,10 @@ impl ShaderProgram {
             if success != gl::TRUE as GLint {
                 let mut len = 0;
                 gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-                let mut buffer = Vec::with_capacity(len as usize);
-                buffer.set_len((len as usize) - 1);
+                // Room for the whole log, which GL ends with a NUL
+                let mut buffer = vec![0u8; len.max(1) as usize];
                 gl::GetProgramInfoLog(program, len, ptr::null_mut(), buffer.as_mut_ptr() as *mut GLchar);
+                buffer.truncate((len as usize).saturating_sub(1));
                 return Err(format!(
                     "Program linking failed: {}",
                     str::from_utf8(&buffer).unwrap_or("Unknown error")
@@ -102,6 +104,16 @@ impl ShaderProgram {
             }
         }
     }
+
+    pub fn set_uniform_vec4(&self, name: &str, x: f32, y: f32, z: f32, w: f32) {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
+            if location != -1 {
+                gl::Uniform4f(location, x, y, z, w);
+            }
+        }
+    }
 }
 
 pub struct Quad {
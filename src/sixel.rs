@@ -0,0 +1,298 @@
+// Sixel decoder for DCS P1;P2;P3 q ... ST
+
+use super::graphics::ImageData;
+
+const MAX_COLOR_REGISTERS: usize = 256;
+
+// VT340 default color registers, as RGB percentages
+const DEFAULT_REGISTERS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    None,
+    Raster,
+    Color,
+    Repeat,
+}
+
+pub struct SixelDecoder {
+    registers: Vec<[u8; 4]>,
+    current_color: usize,
+    // Pixel rows holding a register index + 1, with 0 meaning "never drawn"
+    rows: Vec<Vec<u16>>,
+    x: usize,
+    y: usize,
+    pixel_aspect: usize,
+    transparent_background: bool,
+    command: Command,
+    params: Vec<u32>,
+    max_pixels: usize,
+    exceeded: bool,
+}
+
+impl SixelDecoder {
+    // P1 is the legacy aspect ratio selector, P2 = 1 leaves unset pixels transparent
+    pub fn new(params: &[u16], max_pixels: usize) -> Self {
+        let mut registers = vec![[0, 0, 0, 255]; MAX_COLOR_REGISTERS];
+        for (register, &(r, g, b)) in registers.iter_mut().zip(DEFAULT_REGISTERS.iter()) {
+            *register = [percent(r as u32), percent(g as u32), percent(b as u32), 255];
+        }
+        let pixel_aspect = match params.first().copied().unwrap_or(0) {
+            2 => 5,
+            3 | 4 => 3,
+            5 | 6 => 2,
+            7..=9 => 1,
+            _ => 2,
+        };
+
+        Self {
+            registers,
+            current_color: 0,
+            rows: Vec::new(),
+            x: 0,
+            y: 0,
+            pixel_aspect,
+            transparent_background: params.get(1) == Some(&1),
+            command: Command::None,
+            params: Vec::new(),
+            max_pixels,
+            exceeded: false,
+        }
+    }
+
+    pub fn put(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' if self.command != Command::None => {
+                let param = self.params.last_mut().expect("command always has a parameter slot");
+                *param = param.saturating_mul(10).saturating_add((byte - b'0') as u32);
+                return;
+            }
+            b';' if self.command != Command::None => {
+                self.params.push(0);
+                return;
+            }
+            _ => {}
+        }
+
+        // Any other byte ends the pending parameterized command
+        let repeat = self.finish_command();
+
+        match byte {
+            b'"' => self.start_command(Command::Raster),
+            b'#' => self.start_command(Command::Color),
+            b'!' => self.start_command(Command::Repeat),
+            b'$' => self.x = 0,
+            b'-' => {
+                self.x = 0;
+                self.y += 6 * self.pixel_aspect;
+            }
+            b'?'..=b'~' => self.draw_sixel(byte - b'?', repeat.unwrap_or(1)),
+            _ => {}
+        }
+    }
+
+    fn start_command(&mut self, command: Command) {
+        self.command = command;
+        self.params.clear();
+        self.params.push(0);
+    }
+
+    // Applies the collected command, returning the repeat count for the following sixel
+    fn finish_command(&mut self) -> Option<usize> {
+        let command = std::mem::replace(&mut self.command, Command::None);
+        match command {
+            Command::None => None,
+            Command::Repeat => Some(self.params[0].max(1) as usize),
+            Command::Raster => {
+                // "Pan;Pad;Ph;Pv: pixel aspect numerator/denominator, then the image size
+                if let [pan, pad, ..] = self.params[..] {
+                    if pan > 0 && pad > 0 {
+                        self.pixel_aspect = ((pan + pad / 2) / pad).clamp(1, 10) as usize;
+                    }
+                }
+                if let [_, _, width, height] = self.params[..] {
+                    let (width, height) = (width as usize, height as usize);
+                    if width * height <= self.max_pixels {
+                        self.ensure_size(width, height);
+                    }
+                }
+                None
+            }
+            Command::Color => {
+                let register = self.params[0] as usize % MAX_COLOR_REGISTERS;
+                if let [_, space, a, b, c] = self.params[..] {
+                    self.registers[register] = match space {
+                        1 => hls_to_rgb(a, b, c),
+                        _ => [percent(a), percent(b), percent(c), 255],
+                    };
+                }
+                self.current_color = register;
+                None
+            }
+        }
+    }
+
+    fn ensure_size(&mut self, width: usize, height: usize) {
+        if self.rows.len() < height {
+            self.rows.resize_with(height, Vec::new);
+        }
+        for row in self.rows.iter_mut().take(height) {
+            if row.len() < width {
+                row.resize(width, 0);
+            }
+        }
+    }
+
+    fn draw_sixel(&mut self, bits: u8, repeat: usize) {
+        let width = self.x + repeat;
+        let height = self.y + 6 * self.pixel_aspect;
+        let current_width = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        if width.max(current_width) * height.max(self.rows.len()) > self.max_pixels {
+            self.exceeded = true;
+            self.x += repeat;
+            return;
+        }
+
+        if bits != 0 {
+            let color = self.current_color as u16 + 1;
+            for bit in 0..6 {
+                if bits & (1 << bit) == 0 {
+                    continue;
+                }
+                for dy in 0..self.pixel_aspect {
+                    let y = self.y + bit * self.pixel_aspect + dy;
+                    if self.rows.len() <= y {
+                        self.rows.resize_with(y + 1, Vec::new);
+                    }
+                    let row = &mut self.rows[y];
+                    if row.len() < width {
+                        row.resize(width, 0);
+                    }
+                    row[self.x..width].fill(color);
+                }
+            }
+        }
+        self.x += repeat;
+    }
+
+    pub fn finish(mut self) -> Option<ImageData> {
+        self.finish_command();
+        if self.exceeded {
+            eprintln!("Sixel: image exceeds the {} pixel limit, truncated", self.max_pixels);
+        }
+
+        let width = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let height = self.rows.len();
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let background = if self.transparent_background { [0, 0, 0, 0] } else { self.registers[0] };
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for row in &self.rows {
+            for x in 0..width {
+                let pixel = match row.get(x).copied().unwrap_or(0) {
+                    0 => background,
+                    color => self.registers[color as usize - 1],
+                };
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+        Some(ImageData { width: width as u32, height: height as u32, pixels })
+    }
+}
+
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+// Sixel HLS puts blue at 0 degrees, a 120 degree rotation from the usual hue wheel
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> [u8; 4] {
+    let h = ((hue + 240) % 360) as f32 / 360.0;
+    let l = lightness.min(100) as f32 / 100.0;
+    let s = saturation.min(100) as f32 / 100.0;
+    if s == 0.0 {
+        let v = (l * 255.0) as u8;
+        return [v, v, v, 255];
+    }
+
+    let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p = 2.0 * l - q;
+    let channel = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (v * 255.0).round() as u8
+    };
+    [channel(h + 1.0 / 3.0), channel(h), channel(h - 1.0 / 3.0), 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(params: &[u16], max_pixels: usize, data: &[u8]) -> Option<ImageData> {
+        let mut decoder = SixelDecoder::new(params, max_pixels);
+        data.iter().for_each(|&byte| decoder.put(byte));
+        decoder.finish()
+    }
+
+    fn pixel(image: &ImageData, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * image.width as usize + x) * 4;
+        image.pixels[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn draws_with_rgb_and_hls_registers() {
+        let image = decode(&[0, 1], 1000, b"\"1;1;3;6#1;2;100;0;0~#2;1;0;50;100~#3;2;0;0;100@").unwrap();
+        assert_eq!((image.width, image.height), (3, 6));
+        assert_eq!(pixel(&image, 0, 0), [255, 0, 0, 255]);
+        // HLS hue 0 is blue in sixel
+        let [r, g, b, _] = pixel(&image, 1, 5);
+        assert!(r < 5 && g < 5 && b > 250);
+        assert_eq!(pixel(&image, 2, 0), [0, 0, 255, 255]);
+        // Only the top bit of '@' is set, and the background is transparent
+        assert_eq!(pixel(&image, 2, 1), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn repeats_and_stacks_bands() {
+        let image = decode(&[0, 1], 1000, b"\"1;1#1;2;0;100;0!4~-~").unwrap();
+        assert_eq!((image.width, image.height), (4, 12));
+        assert_eq!(pixel(&image, 3, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 0, 11), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 3, 11), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn stops_drawing_past_the_pixel_limit() {
+        // Two columns of six pixels fit in 12, the third does not
+        let image = decode(&[0, 1], 12, b"\"1;1;100;100~~~").unwrap();
+        assert_eq!((image.width, image.height), (2, 6));
+        assert!(decode(&[0, 1], 12, b"\"1;1!10~").is_none());
+    }
+}
@@ -0,0 +1,99 @@
+// Smart selection: regex rules that make a double-click take a whole URL, path, hash or address
+
+use regex::Regex;
+
+pub const URL_PATTERN: &str = r#"[a-zA-Z][a-zA-Z0-9+.-]*://[^\s<>"'`]*[^\s<>"'`.,;:!?()\[\]{}]"#;
+// Paths with a directory part or an extension, optionally followed by :line or :line:column
+pub const PATH_PATTERN: &str = r"(?:~|\.{1,2}|[\w.@+-]+)?(?:/[\w.@+-]+)+/?(?::\d+(?::\d+)?)?|[\w.@+-]+\.\w+:\d+(?::\d+)?";
+pub const GIT_SHA_PATTERN: &str = r"\b[0-9a-f]{7,40}\b";
+
+// Rules are tried in order, so more specific patterns go before ones that would match a part of them
+#[derive(Clone, Debug)]
+pub struct SelectionRule {
+    pub name: String,
+    pub pattern: String,
+}
+
+impl SelectionRule {
+    fn new(name: &str, pattern: &str) -> Self {
+        Self { name: name.to_string(), pattern: pattern.to_string() }
+    }
+}
+
+pub fn default_rules() -> Vec<SelectionRule> {
+    vec![
+        SelectionRule::new("url", URL_PATTERN),
+        SelectionRule::new("email", r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+"),
+        SelectionRule::new("uuid", r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b"),
+        SelectionRule::new("ipv4", r"\b(?:\d{1,3}\.){3}\d{1,3}(?:/\d{1,2}|:\d{1,5})?\b"),
+        // Either all eight groups or a "::" shorthand, so times like 12:30:45 don't count
+        SelectionRule::new(
+            "ipv6",
+            r"(?:[0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}|(?:[0-9a-fA-F]{1,4}:){1,7}:(?:[0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{0,4}|::(?:[0-9a-fA-F]{1,4}:){0,6}[0-9a-fA-F]{1,4}",
+        ),
+        SelectionRule::new("path", PATH_PATTERN),
+        SelectionRule::new("git sha", GIT_SHA_PATTERN),
+        SelectionRule::new("quoted string", r#""[^"]*"|'[^']*'|`[^`]*`"#),
+    ]
+}
+
+// Rules with invalid patterns are reported and left out
+pub fn compile(rules: &[SelectionRule]) -> Vec<Regex> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            Regex::new(&rule.pattern)
+                .map_err(|e| eprintln!("Selection rule '{}': {}", rule.name, e))
+                .ok()
+        })
+        .collect()
+}
+
+// The [start, end) character range of the first rule matching across `column`
+pub fn token_at(rules: &[Regex], chars: &[char], column: usize) -> Option<(usize, usize)> {
+    let text: String = chars.iter().collect();
+    let byte_offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    let click = *byte_offsets.get(column)?;
+    let to_column = |offset: usize| byte_offsets.partition_point(|&start| start < offset);
+
+    rules.iter().find_map(|rule| {
+        rule.find_iter(&text)
+            .take_while(|found| found.start() <= click)
+            .find(|found| click < found.end())
+            .map(|found| (to_column(found.start()), to_column(found.end())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str, column: usize) -> Option<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let (start, end) = token_at(&compile(&default_rules()), &chars, column)?;
+        Some(chars[start..end].iter().collect())
+    }
+
+    #[test]
+    fn selects_the_first_rule_matching_the_click() {
+        let line = "see https://example.com/a?b=1. or ~/src/main.rs:12:4 now";
+        assert_eq!(token(line, 8).as_deref(), Some("https://example.com/a?b=1"));
+        assert_eq!(token(line, 36).as_deref(), Some("~/src/main.rs:12:4"));
+        assert_eq!(token(line, 1), None);
+    }
+
+    #[test]
+    fn recognizes_addresses_and_hashes() {
+        assert_eq!(token("mail bob.s@host.example.org", 8).as_deref(), Some("bob.s@host.example.org"));
+        assert_eq!(token("ping 10.0.0.1:8080", 7).as_deref(), Some("10.0.0.1:8080"));
+        assert_eq!(token("at 12:30:45", 5), None);
+        assert_eq!(token("commit 1ceed26 done", 9).as_deref(), Some("1ceed26"));
+        assert_eq!(token("say \"two words\"", 7).as_deref(), Some("\"two words\""));
+    }
+
+    #[test]
+    fn invalid_rules_are_left_out() {
+        let rules = compile(&[SelectionRule::new("broken", "("), SelectionRule::new("digits", r"\d+")]);
+        assert_eq!(rules.len(), 1);
+    }
+}
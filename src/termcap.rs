@@ -0,0 +1,107 @@
+// Built-in capability table for XTGETTCAP (DCS + q): the parts of the xterm-256color entry named in TERM
+// that this terminal actually implements, plus the usual extensions for truecolor, styled underlines,
+// cursor shapes, the clipboard and synchronized output
+
+enum Capability {
+    Flag,
+    Value(&'static str),
+}
+
+const CAPABILITIES: &[(&str, Capability)] = &[
+    ("TN", Capability::Value("xterm-256color")),
+    ("name", Capability::Value("xterm-256color")),
+    ("colors", Capability::Value("256")),
+    ("Co", Capability::Value("256")),
+    ("RGB", Capability::Flag),
+    ("Tc", Capability::Flag),
+    ("am", Capability::Flag),
+    ("xenl", Capability::Flag),
+    ("setrgbf", Capability::Value("\x1b[38:2:%p1%d:%p2%d:%p3%dm")),
+    ("setrgbb", Capability::Value("\x1b[48:2:%p1%d:%p2%d:%p3%dm")),
+    ("setaf", Capability::Value("\x1b[%?%p1%{8}%<%t3%p1%d%e%p1%{16}%<%t9%p1%{8}%-%d%e38;5;%p1%d%;m")),
+    ("setab", Capability::Value("\x1b[%?%p1%{8}%<%t4%p1%d%e%p1%{16}%<%t10%p1%{8}%-%d%e48;5;%p1%d%;m")),
+    ("Smulx", Capability::Value("\x1b[4:%p1%dm")),
+    ("Ss", Capability::Value("\x1b[%p1%d q")),
+    ("Se", Capability::Value("\x1b[2 q")),
+    ("Cs", Capability::Value("\x1b]12;%p1%s\x07")),
+    ("Cr", Capability::Value("\x1b]112\x07")),
+    ("Ms", Capability::Value("\x1b]52;%p1%s;%p2%s\x07")),
+    ("Sync", Capability::Value("\x1b[?2026%?%p1%{1}%-%tl%eh%;")),
+    ("XT", Capability::Flag),
+    ("fsl", Capability::Value("\x07")),
+    ("tsl", Capability::Value("\x1b]2;")),
+    ("hs", Capability::Flag),
+    ("bel", Capability::Value("\x07")),
+    ("smso", Capability::Value("\x1b[7m")),
+    ("rmso", Capability::Value("\x1b[27m")),
+    ("smul", Capability::Value("\x1b[4m")),
+    ("rmul", Capability::Value("\x1b[24m")),
+    ("bold", Capability::Value("\x1b[1m")),
+    ("dim", Capability::Value("\x1b[2m")),
+    ("sitm", Capability::Value("\x1b[3m")),
+    ("ritm", Capability::Value("\x1b[23m")),
+    ("smxx", Capability::Value("\x1b[9m")),
+    ("rmxx", Capability::Value("\x1b[29m")),
+    ("rev", Capability::Value("\x1b[7m")),
+    ("invis", Capability::Value("\x1b[8m")),
+    ("sgr0", Capability::Value("\x1b(B\x1b[m")),
+    ("op", Capability::Value("\x1b[39;49m")),
+    // Editing and cursor keys are handled by the local input line and never reach the application,
+    // so the only key-like sequence advertised is the mouse report prefix
+    ("kmous", Capability::Value("\x1b[M")),
+];
+
+// Answers a request carrying ';'-separated hex-encoded capability names
+pub fn xtgettcap_reply(data: &[u8]) -> String {
+    let mut reply = String::new();
+    for hex_name in data.split(|&b| b == b';') {
+        let capability = hex_decode(hex_name)
+            .and_then(|name| CAPABILITIES.iter().find(|(cap, _)| *cap == name))
+            .map(|(_, capability)| capability);
+        let hex_name = String::from_utf8_lossy(hex_name);
+        match capability {
+            Some(Capability::Flag) => reply.push_str(&format!("\x1bP1+r{}\x1b\\", hex_name)),
+            Some(Capability::Value(value)) => {
+                reply.push_str(&format!("\x1bP1+r{}={}\x1b\\", hex_name, hex_encode(value)));
+            }
+            None => reply.push_str(&format!("\x1bP0+r{}\x1b\\", hex_name)),
+        }
+    }
+    reply
+}
+
+fn hex_decode(hex: &[u8]) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = hex
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn hex_encode(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_known_and_unknown_capabilities() {
+        // "TN" and "Tc", then the unknown "kcuu1"
+        let reply = xtgettcap_reply(b"544E;5463;6B63757531");
+        assert_eq!(
+            reply,
+            "\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\\x1bP1+r5463\x1b\\\x1bP0+r6B63757531\x1b\\"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hex() {
+        assert_eq!(xtgettcap_reply(b"544"), "\x1bP0+r544\x1b\\");
+        assert_eq!(xtgettcap_reply(b"zz"), "\x1bP0+rzz\x1b\\");
+    }
+}
@@ -2,10 +2,9 @@ use std::io::{BufRead, BufReader, Write};
 use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
-use nix::sys::termios::{self, InputFlags, LocalFlags};
+use nix::sys::termios;
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
-use std::fs::File;
 
 pub struct Terminal {
     shell: Arc<Mutex<Child>>,
@@ -158,7 +157,7 @@ impl Terminal {
         let filtered_input: Vec<u8> = input
             .iter()
             .copied()
-            .filter(|&c| c.is_ascii_graphic() || c.is_ascii_whitespace() || c == b'\x08') // Allow backspace
+            .filter(|&c| c.is_ascii_graphic() || c.is_ascii_whitespace() || c == b'\x08' || c == b'\x07' || c == b'\x1b' || !c.is_ascii()) // Allow backspace, escape sequences, replies and UTF-8
             .collect();
 
         self.tx
@@ -166,6 +165,15 @@ impl Terminal {
             .map_err(|e| format!("Failed to send input: {}", e))
     }
 
+    // Mouse reports and replies are built by the terminal itself, so they go out byte for byte;
+    // a coordinate of 95 is encoded as 0x7F, which the input filter would drop
+    pub fn write_raw(&self, bytes: &[u8]) -> Result<(), String> {
+        self.tx
+            .send(bytes.to_vec())
+            .map_err(|e| format!("Failed to send input: {}", e))
+    }
+
+    #[allow(dead_code)]
     pub fn read_output(&self) -> Option<Vec<u8>> {
         self.rx_output.lock().unwrap().recv().ok()
     }
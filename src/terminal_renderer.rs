@@ -1,4 +1,6 @@
-use super::terminal_state::TerminalState;
+use super::cursor::CursorShape;
+use super::graphics::VisibleImage;
+use super::terminal_state::{StyledRun, TerminalState, PADDING_LEFT, PADDING_TOP};
 use gl::types::*;
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::surface::Surface;
@@ -7,7 +9,8 @@ use std::rc::Rc;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-const FONT_WIDTH: usize = 8;
+// Kitty places images with a z-index below this under cell backgrounds, not just under text
+const BELOW_BACKGROUNDS_Z: i32 = -1_073_741_824;
 
 pub struct TerminalRenderer<'a, 'b> {
     texture_id: GLuint,
@@ -29,20 +32,51 @@ impl<'a, 'b> TerminalRenderer<'a, 'b> {
         }
     }
 
-    fn calculate_hash(content: &[(String, Color)]) -> u64 {
+    // Forces the next render to redraw, e.g. after palette changes
+    pub fn invalidate(&mut self) {
+        self.last_render_hash = 0;
+    }
+
+    fn calculate_hash(content: &[Vec<StyledRun>], state: &TerminalState) -> u64 {
         let mut hasher = DefaultHasher::new();
-        for (text, color) in content {
-            text.hash(&mut hasher);
-            color.r.hash(&mut hasher);
-            color.g.hash(&mut hasher);
-            color.b.hash(&mut hasher);
+        for runs in content {
+            for run in runs {
+                run.text.hash(&mut hasher);
+                run.column.hash(&mut hasher);
+                for color in std::iter::once(run.fg).chain(run.bg) {
+                    color.r.hash(&mut hasher);
+                    color.g.hash(&mut hasher);
+                    color.b.hash(&mut hasher);
+                }
+                run.underline.hash(&mut hasher);
+                run.strikethrough.hash(&mut hasher);
+            }
+            runs.len().hash(&mut hasher);
+        }
+        state.get_selection().map(|s| (s.normalize(), s.kind)).hash(&mut hasher);
+        state.get_viewport().offset.hash(&mut hasher);
+        state.vi_cursor_cell().hash(&mut hasher);
+        state.visible_hints().hash(&mut hasher);
+        state.cursor_cell().hash(&mut hasher);
+        state.cursor_shape().hash(&mut hasher);
+        state.hovered_link_spans().hash(&mut hasher);
+        state.is_focused().hash(&mut hasher);
+        state.visible_search_matches().hash(&mut hasher);
+        state.search_status().hash(&mut hasher);
+        for image in state.visible_images() {
+            (image.id, image.x, image.y, image.z_index, image.source, image.size).hash(&mut hasher);
         }
         hasher.finish()
     }
 
     pub fn render(&mut self, state: &TerminalState) -> Result<(), String> {
-        let content = state.get_visible_content();
-        let current_hash = Self::calculate_hash(&content);
+        // Keep showing the last complete frame until the synchronized update ends
+        if state.synchronized_update_pending() {
+            return Ok(());
+        }
+
+        let content = state.get_visible_runs();
+        let current_hash = Self::calculate_hash(&content, state);
         
         if current_hash == self.last_render_hash {
             return Ok(());
@@ -62,59 +96,176 @@ impl<'a, 'b> TerminalRenderer<'a, 'b> {
 
         let viewport = state.get_viewport();
         let line_height = viewport.line_height as i32;
-        let mut y_offset = 5; // Small top padding
-        
-        // Render text and selection highlighting
-        for (idx, (text, color)) in content.iter().enumerate() {
+        let cell_width = viewport.cell_width as usize;
+        let line_y = |idx: usize| PADDING_TOP as i32 + idx as i32 * line_height;
+        let images = state.visible_images();
+        let search_matches = state.visible_search_matches();
+
+        draw_images(&mut surface, images.iter().filter(|image| image.z_index < BELOW_BACKGROUNDS_Z))?;
+
+        // Cell backgrounds and selection highlighting
+        for (idx, runs) in content.iter().enumerate() {
+            let y_offset = line_y(idx);
             // Skip if line would be below viewport
             if y_offset >= self.height as i32 {
                 break;
             }
 
+            let line_width = runs.last().map_or(0, |run| run.column + run.text.chars().count());
+
+            for run in runs {
+                if let Some(bg) = run.bg {
+                    let bg_rect = sdl2::rect::Rect::new(
+                        PADDING_LEFT as i32 + (run.column * cell_width) as i32,
+                        y_offset,
+                        (run.text.chars().count() * cell_width) as u32,
+                        line_height as u32,
+                    );
+                    surface.fill_rect(Some(bg_rect), bg)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+
             // Create selection highlight if needed
-            if let Some(selection) = state.get_selection() {
-                let (start, end) = selection.normalize();
-                if idx >= start.line && idx <= end.line {
-                    let start_x = if idx == start.line { 
-                        start.column * FONT_WIDTH 
-                    } else { 
-                        0 
-                    };
-                    let end_x = if idx == end.line { 
-                        end.column * FONT_WIDTH
-                    } else {
-                        text.len() * FONT_WIDTH
-                    };
-
-                    let highlight_rect = sdl2::rect::Rect::new(
-                        start_x as i32,
+            if let Some((start, end)) = state.selection_span(idx, line_width) {
+                let start_x = start * cell_width;
+                let end_x = end * cell_width;
+
+                let highlight_rect = sdl2::rect::Rect::new(
+                    PADDING_LEFT as i32 + start_x as i32,
+                    y_offset,
+                    end_x.saturating_sub(start_x) as u32,
+                    line_height as u32
+                );
+
+                surface.fill_rect(Some(highlight_rect), state.get_settings().colors.selection)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        // Search matches, with the current one standing out
+        for (line, start, end, current) in search_matches {
+            let colors = &state.get_settings().colors;
+            let match_rect = sdl2::rect::Rect::new(
+                PADDING_LEFT as i32 + (start * cell_width) as i32,
+                line_y(line),
+                (end.saturating_sub(start) * cell_width) as u32,
+                line_height as u32,
+            );
+            surface.fill_rect(Some(match_rect), if current { colors.search_current } else { colors.search_match })
+                .map_err(|e| e.to_string())?;
+        }
+
+        // The vi cursor sits under the text so the character it is on stays readable
+        if let Some((line, column)) = state.vi_cursor_cell() {
+            let (x, y, w, h) = viewport.cell_rect(line, column);
+            surface.fill_rect(Some(sdl2::rect::Rect::new(x, y, w, h)), state.get_settings().colors.vi_cursor)
+                .map_err(|e| e.to_string())?;
+        }
+
+        // Negative z-indices sit between the backgrounds and the text
+        draw_images(&mut surface, images.iter().filter(|image| (BELOW_BACKGROUNDS_Z..0).contains(&image.z_index)))?;
+
+        // Render text
+        for (idx, runs) in content.iter().enumerate() {
+            let y_offset = line_y(idx);
+            if y_offset >= self.height as i32 {
+                break;
+            }
+
+            for run in runs {
+                let x = PADDING_LEFT as i32 + (run.column * cell_width) as i32;
+                let run_width = (run.text.chars().count() * cell_width) as u32;
+
+                if !run.text.trim().is_empty() {
+                    let text_surface = self.font.render(&run.text)
+                        .blended(run.fg)
+                        .map_err(|_| format!("Failed to render text: {}", run.text))?;
+
+                    let text_rect = sdl2::rect::Rect::new(
+                        x,
                         y_offset,
-                        (end_x - start_x) as u32,
-                        line_height as u32
+                        text_surface.width(),
+                        text_surface.height()
                     );
 
-                    surface.fill_rect(Some(highlight_rect), state.get_settings().colors.selection)
+                    text_surface.blit(None, &mut surface, text_rect)
                         .map_err(|e| e.to_string())?;
                 }
-            }
 
+                if run.underline {
+                    let underline_rect = sdl2::rect::Rect::new(x, y_offset + line_height - 2, run_width, 1);
+                    surface.fill_rect(Some(underline_rect), run.fg)
+                        .map_err(|e| e.to_string())?;
+                }
+                if run.strikethrough {
+                    let strike_rect = sdl2::rect::Rect::new(x, y_offset + line_height / 2, run_width, 1);
+                    surface.fill_rect(Some(strike_rect), run.fg)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+        }
 
-            // Render text
-            let text_surface = self.font.render(text)
-                .blended(*color)  // Dereference the color
-                .map_err(|_| format!("Failed to render text: {}", text))?;
+        // Images at z-index 0 and above cover the cells they are placed on
+        draw_images(&mut surface, images.iter().filter(|image| image.z_index >= 0))?;
 
-            let text_rect = sdl2::rect::Rect::new(
-                10, // Left margin
-                y_offset,
-                text_surface.width(),
-                text_surface.height()
+        // Underline every visible span of the hovered hyperlink
+        for (line, start, end) in state.hovered_link_spans() {
+            let underline_rect = sdl2::rect::Rect::new(
+                PADDING_LEFT as i32 + (start * cell_width) as i32,
+                PADDING_TOP as i32 + (line as i32 + 1) * line_height - 1,
+                ((end - start) * cell_width) as u32,
+                1,
             );
+            surface.fill_rect(Some(underline_rect), state.get_settings().colors.text)
+                .map_err(|e| e.to_string())?;
+        }
 
-            text_surface.blit(None, &mut surface, text_rect)
+        // Cursor: the DECSCUSR shape while focused, always a hollow box otherwise
+        if let (Some((line, column)), Some(shape)) = (state.cursor_cell(), state.cursor_shape()) {
+            let (x, y, w, h) = viewport.cell_rect(line, column);
+            let cell_rect = sdl2::rect::Rect::new(x, y, w, h);
+            let cursor_color = state.get_settings().colors.cursor;
+            if !state.is_focused() {
+                draw_hollow_rect(&mut surface, cell_rect, cursor_color)?;
+            } else {
+                let cursor_rect = match shape {
+                    CursorShape::Block => cell_rect,
+                    CursorShape::Underline => sdl2::rect::Rect::new(x, y + h as i32 - 2, w, 2),
+                    CursorShape::Bar => sdl2::rect::Rect::new(x, y, 2, h),
+                };
+                surface.fill_rect(Some(cursor_rect), cursor_color)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        // Hint labels cover the start of their match
+        for (line, column, label) in state.visible_hints() {
+            let colors = &state.get_settings().colors;
+            let (x, y, _, h) = viewport.cell_rect(line, column);
+            let label_rect = sdl2::rect::Rect::new(x, y, (label.chars().count() * cell_width) as u32, h);
+            surface.fill_rect(Some(label_rect), colors.hint_background)
+                .map_err(|e| e.to_string())?;
+            let text_surface = self.font.render(&label)
+                .blended(colors.hint_foreground)
                 .map_err(|e| e.to_string())?;
+            text_surface.blit(None, &mut surface, sdl2::rect::Rect::new(x, y, text_surface.width(), text_surface.height()))
+                .map_err(|e| e.to_string())?;
+        }
 
-            y_offset += line_height;
+        // The search bar covers the bottom row while searching
+        if let Some(status) = state.search_status() {
+            let colors = &state.get_settings().colors;
+            let y = line_y(viewport.visible_lines.saturating_sub(1));
+            let bar_rect = sdl2::rect::Rect::new(0, y, self.width as u32, line_height as u32);
+            surface.fill_rect(Some(bar_rect), colors.selection)
+                .map_err(|e| e.to_string())?;
+            let text_surface = self.font.render(&status)
+                .blended(colors.text)
+                .map_err(|e| e.to_string())?;
+            let text_rect = sdl2::rect::Rect::new(PADDING_LEFT as i32, y, text_surface.width(), text_surface.height());
+            text_surface.blit(None, &mut surface, text_rect)
+                .map_err(|e| e.to_string())?;
         }
 
         // Update OpenGL texture
@@ -154,6 +305,46 @@ impl<'a, 'b> TerminalRenderer<'a, 'b> {
     }
 }
 
+// Blits the images in ascending z-index order, cropped to their source rectangle and scaled to their size
+fn draw_images<'i>(surface: &mut Surface, images: impl Iterator<Item = &'i VisibleImage>) -> Result<(), String> {
+    let mut images: Vec<_> = images.collect();
+    images.sort_by_key(|image| image.z_index);
+
+    for image in images {
+        let data = &image.image;
+        let mut image_surface = Surface::new(data.width, data.height, PixelFormatEnum::RGBA32)
+            .map_err(|e| e.to_string())?;
+        let row_bytes = data.width as usize * 4;
+        let pitch = image_surface.pitch() as usize;
+        image_surface.with_lock_mut(|buffer| {
+            for (row, pixels) in data.pixels.chunks_exact(row_bytes).enumerate() {
+                buffer[row * pitch..row * pitch + row_bytes].copy_from_slice(pixels);
+            }
+        });
+
+        let (sx, sy, sw, sh) = image.source;
+        let source_rect = sdl2::rect::Rect::new(sx as i32, sy as i32, sw, sh);
+        let image_rect = sdl2::rect::Rect::new(image.x, image.y, image.size.0, image.size.1);
+        if (sw, sh) == image.size {
+            image_surface.blit(source_rect, surface, image_rect)
+        } else {
+            image_surface.blit_scaled(source_rect, surface, image_rect)
+        }
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn draw_hollow_rect(surface: &mut Surface, rect: sdl2::rect::Rect, color: Color) -> Result<(), String> {
+    let edges = [
+        sdl2::rect::Rect::new(rect.x(), rect.y(), rect.width(), 1),
+        sdl2::rect::Rect::new(rect.x(), rect.bottom() - 1, rect.width(), 1),
+        sdl2::rect::Rect::new(rect.x(), rect.y(), 1, rect.height()),
+        sdl2::rect::Rect::new(rect.right() - 1, rect.y(), 1, rect.height()),
+    ];
+    surface.fill_rects(&edges, color).map_err(|e| e.to_string())
+}
+
 fn create_terminal_texture(width: usize, height: usize) -> GLuint {
     let mut texture_id: GLuint = 0;
     unsafe {
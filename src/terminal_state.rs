@@ -1,29 +1,104 @@
+use regex::Regex;
 use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::rc::Rc;
 use sdl2::pixels::Color;
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::time::{Duration, Instant};
+use vte::{Params, Parser, Perform};
+
+use super::bell::VisualBell;
+use super::cell::{Cell, CellColor, Line, Style};
+use super::charset::{Charset, CharsetState};
+use super::clipboard::{self, ClipboardPolicy, ClipboardRequest, ClipboardTarget};
+use super::cursor::{CursorShape, CursorStyle};
+use super::export::{self, ExportFormat, ExportScope, ExportTheme};
+use super::graphics::{ImageData, ImagePlacement, ImageStore, VisibleImage};
+use super::hints::{self, Hint, HintInput, HintRule, Hints};
+use super::hyperlink::{Hyperlink, HyperlinkId, HyperlinkRegistry};
+use super::iterm2::{self, FileTransfer};
+use super::kitty::{self, KittyGraphics};
+use super::mouse::{MouseProtocol, MouseReport};
+use super::palette;
+use super::reports::{self, ModeStatus};
+use super::scrollback::{self, Scrollback};
+use super::search::{self, Search, SearchMode};
+use super::sixel::SixelDecoder;
+use super::smart_select::{self, SelectionRule};
+use super::termcap;
+use super::title::{TitleState, TitleTarget};
+use super::vi_mode::{self, ViMode, ViMotion};
 
-const MAX_HISTORY_LINES: usize = 1000;
 const MAX_COMMAND_HISTORY: usize = 100;
+const TAB_WIDTH: usize = 8;
+const MAX_DCS_REQUEST_BYTES: usize = 4096;
+const MAX_APC_BYTES: usize = 64 * 1024 * 1024;
+const AUTOSCROLL_INTERVAL: Duration = Duration::from_millis(50);
 
-#[derive(Clone, Debug)]
+pub const PADDING_LEFT: u32 = 10;
+pub const PADDING_TOP: u32 = 5;
+
+// A cell by absolute line id, so selections stay on their text while the view scrolls
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Position {
-    pub line: usize,
+    pub line: u64,
     pub column: usize,
 }
 
+// Simple selections flow like text, semantic ones grow by words, line selections take whole rows
+// and block selections a rectangle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SelectionKind {
+    #[default]
+    Simple,
+    Semantic,
+    Lines,
+    Block,
+}
+
 #[derive(Clone, Debug)]
 pub struct Selection {
     pub start: Position,
     pub end: Position,
+    pub kind: SelectionKind,
+    // The word or line first clicked, which stays selected whichever way the selection grows
+    origin: (Position, Position),
 }
 
 impl Selection {
-    fn new(line: usize, column: usize) -> Self {
-        let pos = Position { line, column };
+    fn new(origin: (Position, Position), kind: SelectionKind) -> Self {
         Self {
-            start: pos.clone(),
-            end: pos,
+            start: origin.0.clone(),
+            end: origin.1.clone(),
+            kind,
+            origin,
+        }
+    }
+
+    // Grows from the origin to cover `range`, the unit under the pointer
+    fn extend_to(&mut self, range: (Position, Position)) {
+        let before = (range.0.line, range.0.column) < (self.origin.0.line, self.origin.0.column);
+        (self.start, self.end) = if before {
+            (range.0, self.origin.1.clone())
+        } else {
+            (self.origin.0.clone(), range.1)
+        };
+    }
+
+    // Selected columns [from, to) on a line holding `row_len` cells
+    pub fn row_span(&self, row: u64, row_len: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.normalize();
+        if row < start.line || row > end.line {
+            return None;
         }
+        Some(match self.kind {
+            SelectionKind::Simple | SelectionKind::Semantic => (
+                if row == start.line { start.column } else { 0 },
+                if row == end.line { end.column } else { row_len },
+            ),
+            SelectionKind::Lines => (0, row_len),
+            SelectionKind::Block => (min(self.start.column, self.end.column), max(self.start.column, self.end.column)),
+        })
     }
 
     pub fn normalize(&self) -> (Position, Position) {
@@ -39,30 +114,197 @@ impl Selection {
 }
 
 pub struct TerminalState {
-    history: VecDeque<String>,
+    history: Scrollback,
     current_input: String,
     cursor_position: usize,
     settings: TerminalSettings,
     viewport: TerminalViewport,
     selection: Option<Selection>,
+    selection_drag: Option<SelectionDrag>,
+    selection_rules: Vec<Regex>,
     command_history: VecDeque<String>,  // Changed from Vec to VecDeque
     command_index: Option<usize>,
-    visible_lines: usize,
+    parser: Parser,
+    pending_line: Line,
+    mouse: MouseProtocol,
+    modes: TerminalModes,
+    focused: bool,
+    titles: TitleState,
+    hyperlinks: HyperlinkRegistry,
+    active_hyperlink: Option<HyperlinkId>,
+    hovered_cell: Option<(usize, usize)>,
+    clipboard_requests: Vec<ClipboardRequest>,
+    style: Style,
+    default_colors: TerminalColors,
+    palette_changed: bool,
+    replies: Vec<u8>,
+    dcs: Option<DcsRequest>,
+    blink_epoch: Instant,
+    // Absolute line the application cursor is on, the line being written unless DECRC moved it back
+    cursor_line: u64,
+    cursor_column: usize,
+    pending_wrap: bool,
+    charsets: CharsetState,
+    saved_cursor: Option<SavedCursor>,
+    tab_stops: Vec<bool>,
+    images: ImageStore,
+    evicted_lines: u64,
+    // Absolute line the screen starts at after the last erase, with the lines above it kept as scrollback
+    screen_top: u64,
+    apc: ApcScanner,
+    apc_body: Vec<u8>,
+    kitty: KittyGraphics,
+    downloads: Vec<FileTransfer>,
+    bell_pending: bool,
+    last_bell: Option<Instant>,
+    search: Option<Search>,
+    vi: Option<ViMode>,
+    hints: Option<Hints>,
+}
+
+// A mouse selection in progress, which keeps scrolling while the pointer is held past the top or bottom
+struct SelectionDrag {
+    column: usize,
+    scroll: i8,
+    last_scroll: Instant,
+}
+
+// vte drops APC strings, so kitty graphics commands are picked out before the parser sees them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ApcScanner {
+    Ground,
+    Escape,
+    Body,
+    BodyEscape,
+}
+
+// State captured by DECSC / CSI s and restored by DECRC / CSI u
+#[derive(Clone, Debug)]
+struct SavedCursor {
+    // Absolute line id, so the saved row stays on its line while output scrolls
+    line: u64,
+    column: usize,
+    style: Style,
+    charsets: CharsetState,
+    origin_mode: bool,
+    pending_wrap: bool,
+}
+
+// A DCS string being collected between hook and unhook
+enum DcsRequest {
+    GetTermcap(Vec<u8>),
+    StatusString(Vec<u8>),
+    Sixel(Box<SixelDecoder>),
+}
+
+// A run of adjacent cells sharing the same resolved colors and decorations
+#[derive(Clone, Debug)]
+pub struct StyledRun {
+    pub text: String,
+    pub column: usize,
+    pub fg: Color,
+    pub bg: Option<Color>,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TerminalModes {
+    pub origin_mode: bool,                    // ?6
+    pub autowrap: bool,                       // ?7
+    pub cursor_visible: bool,                 // ?25
+    pub focus_reporting: bool,                // ?1004
+    pub bracketed_paste: bool,                // ?2004
+    pub synchronized_output: Option<Instant>, // ?2026, with the time the update began
+    pub cursor_style: CursorStyle,            // DECSCUSR, ?12 toggles blinking
+}
+
+impl Default for TerminalModes {
+    fn default() -> Self {
+        Self {
+            origin_mode: false,
+            autowrap: true,
+            cursor_visible: true,
+            focus_reporting: false,
+            bracketed_paste: false,
+            synchronized_output: None,
+            cursor_style: CursorStyle::default(),
+        }
+    }
 }
 
 pub struct TerminalViewport {
     pub offset: usize,
     pub visible_lines: usize,
+    pub columns: usize,
     pub line_height: u32,
+    pub cell_width: u32,
     pub width: u32,
     pub height: u32,
 }
 
+impl TerminalViewport {
+    // Pixel rectangle (x, y, width, height) of a cell
+    pub fn cell_rect(&self, line: usize, column: usize) -> (i32, i32, u32, u32) {
+        (
+            PADDING_LEFT as i32 + (column as u32 * self.cell_width) as i32,
+            PADDING_TOP as i32 + (line as u32 * self.line_height) as i32,
+            self.cell_width,
+            self.line_height,
+        )
+    }
+
+    // Maps a window pixel position to a (line, column) cell, clamped to the grid
+    pub fn cell_at(&self, x: i32, y: i32) -> (usize, usize) {
+        let x = (x - PADDING_LEFT as i32).max(0) as u32;
+        let y = (y - PADDING_TOP as i32).max(0) as u32;
+        let line = min((y / self.line_height) as usize, self.visible_lines.saturating_sub(1));
+        let column = min((x / self.cell_width) as usize, self.columns.saturating_sub(1));
+        (line, column)
+    }
+}
+
 #[derive(Clone)]
 pub struct TerminalSettings {
     pub font_size: u16,
     pub colors: TerminalColors,
     pub prompt: String,
+    pub dim_unfocused: bool,
+    pub title_template: String,
+    pub max_title_length: usize,
+    pub link_opener: String,
+    pub word_separators: String,
+    pub selection_rules: Vec<SelectionRule>,
+    pub hint_rules: Vec<HintRule>,
+    pub hint_alphabet: String,
+    pub editor: String,
+    pub copy_on_select: bool,
+    pub copy_on_select_targets: Vec<ClipboardTarget>,
+    pub trim_selection_whitespace: bool,
+    pub clipboard_read: ClipboardPolicy,
+    pub clipboard_write: ClipboardPolicy,
+    pub clipboard_max_bytes: usize,
+    pub sync_timeout: Duration,
+    pub cursor_blink: bool,
+    pub cursor_blink_interval: Duration,
+    pub image_max_bytes: usize,
+    pub image_memory_limit: usize,
+    pub download_directory: PathBuf,
+    pub export_directory: PathBuf,
+    // Format written by the export hotkey
+    pub export_format: ExportFormat,
+    pub scrollback_lines: usize,
+    pub search_max_matches: usize,
+    pub scrollback_max_bytes: usize,
+    pub scrollback_on_disk: bool,
+    pub scrollback_directory: PathBuf,
+    pub keep_scrollback_file: bool,
+    pub shred_scrollback_file: bool,
+    pub visual_bell: VisualBell,
+    pub audible_bell: bool,
+    pub bell_sound: Option<PathBuf>,
+    pub bell_urgency: bool,
+    pub bell_min_interval: Duration,
 }
 
 #[derive(Clone)]
@@ -70,8 +312,15 @@ pub struct TerminalColors {
     pub text: Color,
     pub background: Color,
     pub selection: Color,
+    pub selection_text: Option<Color>,
+    pub search_match: Color,
+    pub search_current: Color,
     pub cursor: Color,
+    pub vi_cursor: Color,
+    pub hint_foreground: Color,
+    pub hint_background: Color,
     pub input: Color,
+    pub palette: [Color; 256],
 }
 
 impl Default for TerminalColors {
@@ -80,8 +329,15 @@ impl Default for TerminalColors {
             text: Color::RGB(0, 255, 170),
             background: Color::RGB(10, 10, 30),
             selection: Color::RGB(70, 70, 150),
+            selection_text: None,
+            search_match: Color::RGB(120, 90, 20),
+            search_current: Color::RGB(220, 140, 0),
             cursor: Color::RGB(255, 255, 255),
+            vi_cursor: Color::RGB(180, 100, 220),
+            hint_foreground: Color::RGB(10, 10, 30),
+            hint_background: Color::RGB(255, 200, 60),
             input: Color::RGB(200, 200, 255),
+            palette: palette::default_palette(),
         }
     }
 }
@@ -92,58 +348,802 @@ impl Default for TerminalSettings {
             font_size: 16,
             colors: TerminalColors::default(),
             prompt: "$ ".to_string(),
+            dim_unfocused: true,
+            title_template: "{title}".to_string(),
+            max_title_length: 256,
+            link_opener: "xdg-open".to_string(),
+            word_separators: " \t,│`|:\"'()[]{}<>".to_string(),
+            selection_rules: smart_select::default_rules(),
+            hint_rules: hints::default_rules(),
+            hint_alphabet: "jfkdlsahgurieowpq".to_string(),
+            editor: default_editor(),
+            copy_on_select: true,
+            copy_on_select_targets: vec![ClipboardTarget::Primary],
+            trim_selection_whitespace: true,
+            clipboard_read: ClipboardPolicy::Ask,
+            clipboard_write: ClipboardPolicy::Allow,
+            clipboard_max_bytes: 1024 * 1024,
+            sync_timeout: Duration::from_millis(150),
+            cursor_blink: true,
+            cursor_blink_interval: Duration::from_millis(530),
+            image_max_bytes: 64 * 1024 * 1024,
+            image_memory_limit: 256 * 1024 * 1024,
+            download_directory: default_download_directory(),
+            export_directory: default_download_directory(),
+            export_format: ExportFormat::Html,
+            scrollback_lines: 10_000,
+            search_max_matches: 100_000,
+            scrollback_max_bytes: 64 * 1024 * 1024,
+            scrollback_on_disk: false,
+            scrollback_directory: scrollback::default_cache_directory(),
+            keep_scrollback_file: false,
+            shred_scrollback_file: true,
+            visual_bell: VisualBell::Flash,
+            audible_bell: true,
+            bell_sound: None,
+            bell_urgency: true,
+            bell_min_interval: Duration::from_millis(200),
         }
     }
 }
 
 impl TerminalState {
-    pub fn new(width: u32, height: u32, line_height: u32) -> Self {
+    pub fn new(width: u32, height: u32, line_height: u32, cell_width: u32) -> Self {
         let visible_lines = (height / line_height) as usize;
+        let columns = (width.saturating_sub(PADDING_LEFT) / cell_width) as usize;
+        let settings = TerminalSettings::default();
+        let selection_rules = smart_select::compile(&settings.selection_rules);
         Self {
-            history: VecDeque::with_capacity(MAX_HISTORY_LINES),
+            history: new_scrollback(&settings),
             current_input: String::new(),
             cursor_position: 0,
-            settings: TerminalSettings::default(),
+            default_colors: settings.colors.clone(),
+            settings,
             viewport: TerminalViewport {
                 offset: 0,
                 visible_lines,
+                columns,
                 line_height,
+                cell_width,
                 width,
                 height,
             },
             selection: None,
+            selection_drag: None,
+            selection_rules,
             command_history: VecDeque::with_capacity(MAX_COMMAND_HISTORY),
             command_index: None,
-            visible_lines,
+            parser: Parser::new(),
+            pending_line: Line::default(),
+            mouse: MouseProtocol::default(),
+            modes: TerminalModes::default(),
+            focused: true,
+            titles: TitleState::default(),
+            hyperlinks: HyperlinkRegistry::default(),
+            active_hyperlink: None,
+            hovered_cell: None,
+            clipboard_requests: Vec::new(),
+            style: Style::default(),
+            palette_changed: false,
+            replies: Vec::new(),
+            dcs: None,
+            blink_epoch: Instant::now(),
+            cursor_line: 0,
+            cursor_column: 0,
+            pending_wrap: false,
+            charsets: CharsetState::default(),
+            saved_cursor: None,
+            tab_stops: default_tab_stops(columns),
+            images: ImageStore::default(),
+            evicted_lines: 0,
+            screen_top: 0,
+            apc: ApcScanner::Ground,
+            apc_body: Vec::new(),
+            kitty: KittyGraphics::default(),
+            downloads: Vec::new(),
+            bell_pending: false,
+            last_bell: None,
+            search: None,
+            vi: None,
+            hints: None,
         }
     }
 
-    // Selection handling
-    pub fn start_selection(&mut self, line: usize, column: usize) {
-        let content = self.get_visible_content();
-        if line >= content.len() {
+    // Fits the grid to a new window size, keeping the scrollback and the rest of the state
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let columns = (width.saturating_sub(PADDING_LEFT) / self.viewport.cell_width) as usize;
+        self.viewport.width = width;
+        self.viewport.height = height;
+        self.viewport.visible_lines = (height / self.viewport.line_height) as usize;
+        // Stops the application set stay put, and new columns get the default ones
+        self.tab_stops.resize(columns, false);
+        for column in self.viewport.columns..columns {
+            self.tab_stops[column] = column > 0 && column % TAB_WIDTH == 0;
+        }
+        self.viewport.columns = columns;
+        self.cursor_column = min(self.cursor_column, self.last_column());
+        self.pending_wrap = false;
+        self.viewport.offset = min(self.viewport.offset, self.screen_start());
+    }
+
+    // Images
+    // Places an image at the cursor, scaled to the given pixel size, and moves the cursor to the line below it
+    fn place_image(&mut self, image: ImageData, size: (u32, u32), move_cursor: bool) {
+        let line = self.evicted_lines + self.history.len() as u64;
+        let cell_size = (self.viewport.cell_width, self.viewport.line_height);
+        let mut placement = ImagePlacement::new(Rc::new(image), line, self.cursor_column, cell_size);
+        placement.size = size;
+        placement.rows = size.1.div_ceil(cell_size.1).max(1) as usize;
+        placement.columns = size.0.div_ceil(cell_size.0).max(1) as usize;
+        let rows = placement.rows;
+        self.images.place(placement, self.settings.image_memory_limit);
+        if move_cursor {
+            for _ in 0..rows {
+                self.push_line();
+            }
+        }
+    }
+
+    // OSC 1337 File=: inline images go on the grid, anything else waits for the user to accept the download
+    fn file_transfer(&mut self, args: &[&[u8]]) {
+        let args = args.join(&b';');
+        let Some(transfer) = iterm2::parse_file(&args, self.settings.image_max_bytes) else {
+            return;
+        };
+        if !transfer.inline {
+            self.downloads.push(transfer);
+            return;
+        }
+
+        let image = match ImageData::decode(&transfer.data, self.settings.image_max_bytes) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("OSC 1337: failed to decode inline image: {}", e);
+                return;
+            }
+        };
+        let cell_size = (self.viewport.cell_width, self.viewport.line_height);
+        let screen = (
+            self.viewport.columns as u32 * self.viewport.cell_width,
+            self.viewport.visible_lines as u32 * self.viewport.line_height,
+        );
+        let size = transfer.display_size((image.width, image.height), cell_size, screen);
+        self.place_image(image, size, transfer.move_cursor);
+    }
+
+    pub fn take_downloads(&mut self) -> Vec<FileTransfer> {
+        std::mem::take(&mut self.downloads)
+    }
+
+    fn kitty_graphics(&mut self, body: &[u8]) {
+        let context = kitty::Context {
+            cursor_line: self.evicted_lines + self.history.len() as u64,
+            cursor_column: self.cursor_column,
+            first_visible_line: self.line_id(self.screen_start()),
+            cell_size: (self.viewport.cell_width, self.viewport.line_height),
+            max_image_bytes: self.settings.image_max_bytes,
+            memory_limit: self.settings.image_memory_limit,
+        };
+        let outcome = self.kitty.handle(body, &mut self.images, &context);
+        if let Some(reply) = outcome.reply {
+            self.reply(&reply);
+        }
+
+        // The cursor ends up on the image's last row, just past its right edge
+        if let Some((rows, columns)) = outcome.cursor_advance {
+            let column = self.cursor_column + columns;
+            for _ in 1..rows {
+                self.push_line();
+            }
+            self.cursor_column = min(column, self.last_column());
+        }
+    }
+
+    // Every placement overlapping the viewport, in window pixels
+    pub fn visible_images(&self) -> Vec<VisibleImage> {
+        let (start, end) = self.get_visible_range();
+        let first_visible = self.evicted_lines + start as u64;
+        let last_visible = self.evicted_lines + end as u64;
+
+        self.images
+            .placements()
+            .iter()
+            .filter(|p| p.line + p.rows as u64 > first_visible && p.line <= last_visible)
+            .map(|p| {
+                let row = p.line as i64 - first_visible as i64;
+                let x = PADDING_LEFT as i32 + (p.column as u32 * self.viewport.cell_width + p.offset.0) as i32;
+                let y = PADDING_TOP as i32 + (row * self.viewport.line_height as i64) as i32 + p.offset.1 as i32;
+                VisibleImage {
+                    id: p.id,
+                    image: Rc::clone(&p.image),
+                    x,
+                    y,
+                    z_index: p.z_index,
+                    source: p.source,
+                    size: p.size,
+                }
+            })
+            .collect()
+    }
+
+    // Cursor save/restore and resets
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            line: self.cursor_line,
+            column: self.cursor_column,
+            style: self.style,
+            charsets: self.charsets,
+            origin_mode: self.modes.origin_mode,
+            pending_wrap: self.pending_wrap,
+        });
+    }
+
+    // Without a saved state the cursor goes home with default attributes
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.clone().unwrap_or(SavedCursor {
+            line: self.line_id(self.screen_start()),
+            column: 0,
+            style: Style::default(),
+            charsets: CharsetState::default(),
+            origin_mode: false,
+            pending_wrap: false,
+        });
+        // Lines evicted since the save leave the cursor on the oldest one still around
+        self.cursor_line = saved.line.clamp(self.evicted_lines, self.input_line_id());
+        self.cursor_column = min(saved.column, self.last_column());
+        self.style = saved.style;
+        self.charsets = saved.charsets;
+        self.modes.origin_mode = saved.origin_mode;
+        self.pending_wrap = saved.pending_wrap;
+    }
+
+    // RIS: back to the power-on state, including screen contents, palette and titles
+    fn full_reset(&mut self) {
+        self.clear();
+        self.pending_line = Line::default();
+        self.cursor_column = 0;
+        self.pending_wrap = false;
+        self.style = Style::default();
+        self.charsets = CharsetState::default();
+        self.saved_cursor = None;
+        self.tab_stops = default_tab_stops(self.viewport.columns);
+        self.modes = TerminalModes::default();
+        self.mouse = MouseProtocol::default();
+        self.settings.colors = self.default_colors.clone();
+        self.palette_changed = true;
+        self.titles = TitleState::default();
+        self.hyperlinks = HyperlinkRegistry::default();
+        self.active_hyperlink = None;
+        self.dcs = None;
+        self.images = ImageStore::default();
+        self.kitty = KittyGraphics::default();
+    }
+
+    // DECSTR: the documented soft subset, which leaves screen contents, palette and tab stops alone
+    fn soft_reset(&mut self) {
+        self.modes.cursor_visible = true;
+        self.modes.origin_mode = false;
+        self.modes.autowrap = false;
+        self.pending_wrap = false;
+        self.style = Style::default();
+        self.charsets = CharsetState::default();
+        self.saved_cursor = None;
+    }
+
+    fn last_column(&self) -> usize {
+        self.viewport.columns.max(1) - 1
+    }
+
+    fn next_tab_stop(&self) -> usize {
+        (self.cursor_column + 1..self.tab_stops.len())
+            .find(|&column| self.tab_stops[column])
+            .unwrap_or(self.last_column())
+    }
+
+    // DECRQSS: reports the setting selected by the request, e.g. "m" for SGR
+    fn status_string_reply(&self, request: &[u8]) -> String {
+        let setting = match request {
+            b"m" => Some(format!("{}m", self.style.to_sgr())),
+            b"r" => Some(format!("1;{}r", self.viewport.visible_lines)),
+            b" q" => Some(format!("{} q", self.modes.cursor_style.decscusr())),
+            _ => None,
+        };
+        match setting {
+            Some(setting) => format!("\x1bP1$r{}\x1b\\", setting),
+            None => "\x1bP0$r\x1b\\".to_string(),
+        }
+    }
+
+    // Replies to queries, to be written back to the application
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    fn reply(&mut self, reply: &str) {
+        self.replies.extend_from_slice(reply.as_bytes());
+    }
+
+    // Bells closer together than the minimum interval are dropped, so a flood of BEL stays cheap
+    fn ring_bell(&mut self) {
+        let now = Instant::now();
+        if self.last_bell.is_some_and(|last| now.duration_since(last) < self.settings.bell_min_interval) {
+            return;
+        }
+        self.last_bell = Some(now);
+        self.bell_pending = true;
+    }
+
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::replace(&mut self.bell_pending, false)
+    }
+
+    // Dynamic colors
+    pub fn take_palette_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.palette_changed, false)
+    }
+
+    // OSC 4 ; index ; spec pairs, where a "?" spec queries the current value
+    fn set_palette_colors(&mut self, params: &[&[u8]], terminator: &str) {
+        for pair in params.chunks_exact(2) {
+            let Some(index) = std::str::from_utf8(pair[0]).ok().and_then(|i| i.parse::<u8>().ok()) else {
+                continue;
+            };
+            let spec = String::from_utf8_lossy(pair[1]);
+            if spec == "?" {
+                let color = palette::format_color_spec(self.settings.colors.palette[index as usize]);
+                self.reply(&format!("\x1b]4;{};{}{}", index, color, terminator));
+            } else if let Some(color) = palette::parse_color_spec(&spec) {
+                self.settings.colors.palette[index as usize] = color;
+                self.palette_changed = true;
+            }
+        }
+    }
+
+    fn reset_palette_colors(&mut self, params: &[&[u8]]) {
+        let indices: Vec<u8> = params
+            .iter()
+            .filter_map(|p| std::str::from_utf8(p).ok()?.parse().ok())
+            .collect();
+        if indices.is_empty() {
+            self.settings.colors.palette = self.default_colors.palette;
+        }
+        for index in indices {
+            self.settings.colors.palette[index as usize] = self.default_colors.palette[index as usize];
+        }
+        self.palette_changed = true;
+    }
+
+    // OSC 10/11/12/17/19; extra specs apply to the following color numbers, as in xterm
+    fn set_dynamic_colors(&mut self, first: u16, specs: &[&[u8]], terminator: &str) {
+        for (code, spec) in (first..).zip(specs) {
+            let spec = String::from_utf8_lossy(spec);
+            if spec == "?" {
+                if let Some(color) = self.dynamic_color(code) {
+                    self.reply(&format!("\x1b]{};{}{}", code, palette::format_color_spec(color), terminator));
+                }
+            } else if let Some(color) = palette::parse_color_spec(&spec) {
+                self.set_dynamic_color(code, Some(color));
+            }
+        }
+    }
+
+    fn dynamic_color(&self, code: u16) -> Option<Color> {
+        let colors = &self.settings.colors;
+        match code {
+            10 => Some(colors.text),
+            11 => Some(colors.background),
+            12 => Some(colors.cursor),
+            17 => Some(colors.selection),
+            19 => Some(colors.selection_text.unwrap_or(colors.text)),
+            _ => None,
+        }
+    }
+
+    // Setting a color to None restores its default
+    fn set_dynamic_color(&mut self, code: u16, color: Option<Color>) {
+        let defaults = &self.default_colors;
+        let colors = &mut self.settings.colors;
+        match code {
+            10 => colors.text = color.unwrap_or(defaults.text),
+            11 => colors.background = color.unwrap_or(defaults.background),
+            12 => colors.cursor = color.unwrap_or(defaults.cursor),
+            17 => colors.selection = color.unwrap_or(defaults.selection),
+            19 => colors.selection_text = color.or(defaults.selection_text),
+            _ => return,
+        }
+        self.palette_changed = true;
+    }
+
+    fn resolve_color(&self, color: CellColor, default: Color) -> Color {
+        match color {
+            CellColor::Default => default,
+            CellColor::Indexed(index) => self.settings.colors.palette[index as usize],
+            CellColor::Rgb(r, g, b) => Color::RGB(r, g, b),
+        }
+    }
+
+    fn is_selected(&self, row: usize, column: usize) -> bool {
+        self.selection_span(row, usize::MAX).is_some_and(|(start, end)| (start..end).contains(&column))
+    }
+
+    // Foreground and optional background of a cell after bold, inverse, dim and hidden are applied
+    fn cell_colors(&self, cell: &Cell) -> (Color, Option<Color>) {
+        let colors = &self.settings.colors;
+        let style = cell.style;
+        // Bold renders the eight basic colors in their bright variants
+        let fg_color = match style.fg {
+            CellColor::Indexed(index) if style.bold && index < 8 => CellColor::Indexed(index + 8),
+            fg => fg,
+        };
+        let mut fg = self.resolve_color(fg_color, colors.text);
+        let mut bg = match style.bg {
+            CellColor::Default => None,
+            bg => Some(self.resolve_color(bg, colors.background)),
+        };
+        if style.inverse {
+            let inverted_bg = fg;
+            fg = bg.unwrap_or(colors.background);
+            bg = Some(inverted_bg);
+        }
+        if style.dim {
+            fg = Color::RGB(fg.r / 2, fg.g / 2, fg.b / 2);
+        }
+        if style.hidden {
+            fg = bg.unwrap_or(colors.background);
+        }
+        (fg, bg)
+    }
+
+    // Resolves a line's cells to colors and groups them into runs for the renderer
+    fn line_runs(&self, line_idx: usize, line: &Line) -> Vec<StyledRun> {
+        let colors = &self.settings.colors;
+        let mut runs: Vec<StyledRun> = Vec::new();
+
+        for (column, cell) in line.cells.iter().enumerate() {
+            let style = cell.style;
+            let (mut fg, bg) = self.cell_colors(cell);
+            if let Some(selection_text) = colors.selection_text.filter(|_| self.is_selected(line_idx, column)) {
+                fg = selection_text;
+            }
+
+            match runs.last_mut() {
+                Some(run)
+                    if run.fg == fg
+                        && run.bg == bg
+                        && run.underline == style.underline
+                        && run.strikethrough == style.strikethrough =>
+                {
+                    run.text.push(cell.c);
+                }
+                _ => runs.push(StyledRun {
+                    text: cell.c.to_string(),
+                    column,
+                    fg,
+                    bg,
+                    underline: style.underline,
+                    strikethrough: style.strikethrough,
+                }),
+            }
+        }
+        runs
+    }
+
+    // OSC 52 requests waiting for the window layer, which owns the clipboard
+    pub fn take_clipboard_requests(&mut self) -> Vec<ClipboardRequest> {
+        std::mem::take(&mut self.clipboard_requests)
+    }
+
+    // Hyperlinks
+    pub fn hyperlink_at(&self, line: usize, column: usize) -> Option<&Hyperlink> {
+        let id = self.visible_line(line)?.cells.get(column)?.hyperlink?;
+        self.hyperlinks.get(id)
+    }
+
+    pub fn set_hovered_cell(&mut self, cell: Option<(usize, usize)>) {
+        self.hovered_cell = cell;
+    }
+
+    // (line, start column, end column) spans of the link under the mouse, across all visible lines
+    pub fn hovered_link_spans(&self) -> Vec<(usize, usize, usize)> {
+        let Some(id) = self.hovered_cell
+            .and_then(|(line, column)| self.visible_line(line)?.cells.get(column)?.hyperlink)
+        else {
+            return Vec::new();
+        };
+
+        let (start, end) = self.get_visible_range();
+        self.history
+            .range(start, end)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(idx, line)| {
+                line.hyperlink_spans(id).into_iter().map(move |(s, e)| (idx, s, e))
+            })
+            .collect()
+    }
+
+    // Link targets of the cells covered by the selection, in order and without duplicates
+    pub fn get_selected_links(&self) -> Vec<String> {
+        let Some(selection) = &self.selection else {
+            return Vec::new();
+        };
+
+        let mut links: Vec<String> = Vec::new();
+        for row in self.selection_rows(selection) {
+            for cell in &row.cells {
+                if let Some(link) = cell.hyperlink.and_then(|id| self.hyperlinks.get(id)) {
+                    if !links.contains(&link.uri) {
+                        links.push(link.uri.clone());
+                    }
+                }
+            }
+        }
+        links
+    }
+
+    fn visible_line(&self, line: usize) -> Option<Line> {
+        let (start, end) = self.get_visible_range();
+        (start + line < end).then(|| self.history.get(start + line)).flatten()
+    }
+
+    // Window title built from the application title and the last command
+    pub fn window_title(&self) -> String {
+        let command = self.command_history.back().map(String::as_str).unwrap_or("");
+        self.titles.format(&self.settings.title_template, command, self.settings.max_title_length)
+    }
+
+    // Focus handling, returning the report to send when the application asked for focus events
+    pub fn set_focused(&mut self, focused: bool) -> Option<&'static [u8]> {
+        self.focused = focused;
+        if !self.modes.focus_reporting {
+            return None;
+        }
+        Some(if focused { b"\x1b[I" } else { b"\x1b[O" })
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // Mouse reporting
+    pub fn mouse_reporting_active(&self) -> bool {
+        self.mouse.is_active()
+    }
+
+    pub fn encode_mouse_report(&mut self, report: &MouseReport) -> Option<Vec<u8>> {
+        self.mouse.encode(report)
+    }
+
+    fn set_title(&mut self, target: TitleTarget, params: &[&[u8]]) {
+        // The title itself may contain semicolons, which the parser splits on
+        let text = params
+            .iter()
+            .map(|p| String::from_utf8_lossy(p))
+            .collect::<Vec<_>>()
+            .join(";");
+        self.titles.set(target, &text, self.settings.max_title_length);
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        if self.mouse.set_mode(mode, enabled) {
             return;
         }
 
-        let line_content = &content[line].0;
-        let bounded_column = min(column, line_content.len());
-        self.selection = Some(Selection::new(line, bounded_column));
+        match mode {
+            6 => {
+                self.modes.origin_mode = enabled;
+                self.cursor_column = 0;
+            }
+            7 => self.modes.autowrap = enabled,
+            12 => self.modes.cursor_style.blinking = enabled,
+            25 => self.modes.cursor_visible = enabled,
+            1004 => self.modes.focus_reporting = enabled,
+            2004 => self.modes.bracketed_paste = enabled,
+            2026 => self.modes.synchronized_output = enabled.then(Instant::now),
+            _ => {}
+        }
+    }
+
+    // True while an application is mid-way through a synchronized update that hasn't timed out
+    pub fn synchronized_update_pending(&self) -> bool {
+        self.modes.synchronized_output
+            .is_some_and(|started| started.elapsed() < self.settings.sync_timeout)
+    }
+
+    fn private_mode_status(&self, mode: u16) -> ModeStatus {
+        if let Some(enabled) = self.mouse.mode_enabled(mode) {
+            return enabled.into();
+        }
+
+        match mode {
+            6 => self.modes.origin_mode.into(),
+            7 => self.modes.autowrap.into(),
+            12 => self.modes.cursor_style.blinking.into(),
+            25 => self.modes.cursor_visible.into(),
+            1004 => self.modes.focus_reporting.into(),
+            2004 => self.modes.bracketed_paste.into(),
+            2026 => self.modes.synchronized_output.is_some().into(),
+            _ => ModeStatus::NotRecognized,
+        }
+    }
+
+    // ANSI modes: insert mode and automatic newline are not supported
+    fn ansi_mode_status(&self, mode: u16) -> ModeStatus {
+        match mode {
+            4 | 20 => ModeStatus::PermanentlyReset,
+            _ => ModeStatus::NotRecognized,
+        }
+    }
+
+    // Position of the application's output cursor, 1-based, counted from the top of the visible rows
+    fn application_cursor(&self) -> (usize, usize) {
+        let (start, _) = self.get_visible_range();
+        (self.line_index(self.cursor_line).saturating_sub(start) + 1, self.cursor_column + 1)
+    }
+
+    // XTSMGRAPHICS: only reading the color register count and maximum sixel geometry is supported
+    fn graphics_attribute_reply(&self, item: u16, action: u16) -> String {
+        match (item, action) {
+            (1, 1) | (1, 4) => format!("\x1b[?1;0;{}S", 256),
+            (2, 1) | (2, 4) => format!("\x1b[?2;0;{};{}S", self.viewport.width, self.viewport.height),
+            (1, _) | (2, _) => format!("\x1b[?{};3;0S", item),
+            _ => format!("\x1b[?{};1;0S", item),
+        }
+    }
+
+    fn device_status_report(&mut self, request: u16, private: bool) {
+        match request {
+            5 if !private => self.reply(reports::STATUS_OK),
+            6 => {
+                let (line, column) = self.application_cursor();
+                self.reply(&reports::cursor_position(line, column, private));
+            }
+            _ => {}
+        }
+    }
+
+    // Selection handling
+    // Starts a selection on a cell; `clicks` picks cells, words or whole lines, Alt-drags make blocks
+    pub fn start_selection(&mut self, line: usize, column: usize, clicks: u8, block: bool) {
+        if line >= self.visible_row_count() {
+            return;
+        }
+        let kind = match clicks {
+            _ if block => SelectionKind::Block,
+            2 => SelectionKind::Semantic,
+            n if n >= 3 => SelectionKind::Lines,
+            _ => SelectionKind::Simple,
+        };
+        let range = self.selection_unit(kind, self.visible_line_id(line), column);
+        self.selection = Some(Selection::new(range, kind));
+        self.selection_drag = Some(SelectionDrag { column, scroll: 0, last_scroll: Instant::now() });
     }
 
     pub fn update_selection(&mut self, line: usize, column: usize) {
-        let content = self.get_visible_content();
+        let Some(kind) = self.selection.as_ref().map(|selection| selection.kind) else {
+            return;
+        };
+        let line = min(line, self.visible_row_count().saturating_sub(1));
+        let range = self.selection_unit(kind, self.visible_line_id(line), column);
         if let Some(selection) = &mut self.selection {
-            let bounded_line = min(line, content.len().saturating_sub(1));
-            let line_content = &content[bounded_line].0;
-            let bounded_column = min(column, line_content.len());
-            println!("UPDATED SELECTION: {:#?}", self.current_input);
-            selection.end = Position {
-                line: bounded_line,
-                column: bounded_column,
-            };
+            selection.extend_to(range);
+        }
+    }
+
+    // Shift+click moves the end of the selection nearest the click, keeping the other end fixed
+    pub fn extend_selection(&mut self, line: usize, column: usize) {
+        let id = self.visible_line_id(line);
+        let Some(selection) = &mut self.selection else {
+            return self.start_selection(line, column, 1, false);
+        };
+        let (start, end) = selection.normalize();
+        let fixed = if (id, column) < (start.line, start.column) { end } else { start };
+        selection.origin = (fixed.clone(), fixed);
+        self.selection_drag = Some(SelectionDrag { column, scroll: 0, last_scroll: Instant::now() });
+        self.update_selection(line, column);
+    }
+
+    // Extends the selection to the pointer, noting when it is past the top or bottom so the view follows
+    pub fn drag_selection(&mut self, x: i32, y: i32) {
+        let (line, column) = self.viewport.cell_at(x, y);
+        let top = PADDING_TOP as i32;
+        let bottom = top + (self.visible_row_count() as u32 * self.viewport.line_height) as i32;
+        if let Some(drag) = &mut self.selection_drag {
+            drag.column = column;
+            drag.scroll = if y < top { -1 } else if y >= bottom { 1 } else { 0 };
+        }
+        self.update_selection(line, column);
+    }
+
+    pub fn end_selection_drag(&mut self) {
+        self.selection_drag = None;
+    }
+
+    // Called every frame, so a drag held past an edge keeps scrolling without the mouse moving
+    pub fn autoscroll_selection(&mut self) {
+        let Some(drag) = &mut self.selection_drag else {
+            return;
+        };
+        if drag.scroll == 0 || drag.last_scroll.elapsed() < AUTOSCROLL_INTERVAL {
+            return;
+        }
+        drag.last_scroll = Instant::now();
+        let (scroll, column) = (drag.scroll, drag.column);
+        if scroll < 0 {
+            self.scroll_up(1);
+            self.update_selection(0, column);
+        } else {
+            self.scroll_down(1);
+            self.update_selection(usize::MAX, column);
+        }
+    }
+
+    // The cells, word or logical line at a cell, as a [start, end) range
+    fn selection_unit(&self, kind: SelectionKind, id: u64, column: usize) -> (Position, Position) {
+        let index = self.line_index(id);
+        let row = self.buffer_row(index);
+        match kind {
+            SelectionKind::Simple | SelectionKind::Block => {
+                let position = Position { line: id, column: min(column, row.len()) };
+                (position.clone(), position)
+            }
+            SelectionKind::Semantic if column < row.len() => {
+                // Rules see the whole logical line, so a URL or path can run across soft wraps
+                let (first, last) = self.logical_line(index);
+                let rows: Vec<Line> = (first..=last).map(|index| self.buffer_row(index)).collect();
+                let lengths: Vec<usize> = rows.iter().map(Line::len).collect();
+                let chars: Vec<char> = rows.iter().flat_map(|row| row.cells.iter().map(|cell| cell.c)).collect();
+                let click = lengths[..index - first].iter().sum::<usize>() + column;
+                let (start, end) = smart_select::token_at(&self.selection_rules, &chars, click)
+                    .unwrap_or_else(|| word_range(&chars, click, &self.settings.word_separators));
+                let position = |offset: usize, at_end: bool| {
+                    let (row, column) = locate(&lengths, offset, at_end);
+                    Position { line: self.line_id(first + row), column }
+                };
+                (position(start, false), position(end, true))
+            }
+            SelectionKind::Semantic => {
+                let position = Position { line: id, column };
+                (position.clone(), position)
+            }
+            SelectionKind::Lines => {
+                let (first, last) = self.logical_line(index);
+                let end = Position { line: self.line_id(last), column: self.buffer_row(last).len() };
+                (Position { line: self.line_id(first), column: 0 }, end)
+            }
+        }
+    }
+
+    // First and last row of the logical line a row belongs to, following soft wraps
+    fn logical_line(&self, index: usize) -> (usize, usize) {
+        let mut first = index;
+        while first > 0 && self.buffer_row(first - 1).wrapped {
+            first -= 1;
+        }
+        let mut last = index;
+        while last < self.history.len() && self.buffer_row(last).wrapped {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    // Selected columns on a visible row
+    pub fn selection_span(&self, row: usize, row_len: usize) -> Option<(usize, usize)> {
+        self.selection.as_ref()?.row_span(self.visible_line_id(row), row_len)
+    }
+
+    // Drops the selection once text under it changes: lines evicted from the scrollback, or anything
+    // from `changed` on, such as the input line being edited
+    fn invalidate_selection(&mut self, changed: u64) {
+        let stale = self.selection.as_ref().is_some_and(|selection| {
+            let (start, end) = selection.normalize();
+            start.line < self.evicted_lines || end.line >= changed
+        });
+        if stale {
+            self.selection = None;
+            self.selection_drag = None;
         }
     }
-    
 
     pub fn clear_selection(&mut self) {
         self.selection = None;
@@ -161,32 +1161,110 @@ impl TerminalState {
         }
     }
 
+    // Soft-wrapped rows are one line of text, except in blocks where every row stands alone
     fn get_text_from_selection(&self, selection: &Selection) -> String {
-        let visible_content = self.get_visible_content();
-        let (start_pos, end_pos) = selection.normalize();
-
         let mut result = String::new();
-        for (i, (line, _)) in visible_content.iter().enumerate() {
-            if i < start_pos.line || i > end_pos.line {
-                continue;
+        let mut line = String::new();
+        for row in self.selection_rows(selection) {
+            line.extend(row.cells.iter().map(|cell| cell.c));
+            if !row.wrapped {
+                let text = if self.settings.trim_selection_whitespace { line.trim_end() } else { &line };
+                result.push_str(text);
+                result.push('\n');
+                line.clear();
             }
+        }
+        result.pop();
+        result
+    }
 
-            let line_start = if i == start_pos.line { start_pos.column } else { 0 };
-            let line_end = if i == end_pos.line {
-                min(end_pos.column, line.len())
-            } else {
-                line.len()
-            };
+    // Rows covered by a selection, cut to the selected columns
+    fn selection_rows(&self, selection: &Selection) -> Vec<Line> {
+        let (start, end) = selection.normalize();
+        (self.line_index(start.line)..=self.line_index(end.line))
+            .map(|index| {
+                let id = self.line_id(index);
+                let mut row = self.buffer_row(index);
+                let (first, last) = selection.row_span(id, row.len()).unwrap_or_default();
+                let last = min(last, row.len());
+                row.cells = row.cells.get(first..last.max(first)).unwrap_or(&[]).to_vec();
+                // A row cut short no longer continues into the next one
+                row.wrapped &= id != end.line && selection.kind != SelectionKind::Block;
+                row
+            })
+            .collect()
+    }
 
-            if !result.is_empty() {
-                result.push('\n');
-            }
+    // A history row, or the input line one past the end of the history
+    fn buffer_row(&self, index: usize) -> Line {
+        if index < self.history.len() {
+            self.history.get(index).unwrap_or_default()
+        } else {
+            self.input_line()
+        }
+    }
 
-            if line_start < line.len() {
-                result.push_str(&line[line_start..line_end]);
+    // History index of an absolute line, clamped to the lines still around
+    fn line_index(&self, id: u64) -> usize {
+        min(id.saturating_sub(self.evicted_lines) as usize, self.history.len())
+    }
+
+    fn line_id(&self, index: usize) -> u64 {
+        self.evicted_lines + index as u64
+    }
+
+    // Absolute id of a visible row, where the row after the history is the input line
+    fn visible_line_id(&self, row: usize) -> u64 {
+        let (start, _) = self.get_visible_range();
+        self.line_id(start + row)
+    }
+
+    fn input_line_id(&self) -> u64 {
+        self.line_id(self.history.len())
+    }
+
+    fn visible_row_count(&self) -> usize {
+        let (start, end) = self.get_visible_range();
+        end - start + usize::from(self.viewport.offset == 0)
+    }
+
+    // Export
+    pub fn export(&self, format: ExportFormat, scope: ExportScope) -> String {
+        let rows = match scope {
+            ExportScope::Scrollback => {
+                let mut rows = self.history.range(0, self.history.len());
+                rows.push(self.input_line());
+                rows
+            }
+            ExportScope::Screen => {
+                let (start, end) = self.get_visible_range();
+                let mut rows = self.history.range(start, end);
+                if self.viewport.offset == 0 {
+                    rows.push(self.input_line());
+                }
+                rows
             }
+            ExportScope::Selection => self.selection.as_ref().map(|selection| self.selection_rows(selection)).unwrap_or_default(),
+        };
+
+        let resolve = |cell: &Cell| self.cell_colors(cell);
+        let theme = ExportTheme {
+            foreground: self.settings.colors.text,
+            background: self.settings.colors.background,
+            cell_size: (self.viewport.cell_width, self.viewport.line_height),
+            font_size: self.settings.font_size,
+            resolve: &resolve,
+        };
+        export::export(format, &export::rejoin(rows), &theme)
+    }
+
+    // The prompt and input as the last row of the screen
+    fn input_line(&self) -> Line {
+        let text = format!("{}{}", self.settings.prompt, self.current_input);
+        Line {
+            cells: text.chars().map(|c| Cell { c, ..Cell::default() }).collect(),
+            wrapped: false,
         }
-        result
     }
 
     // Command history handling
@@ -200,6 +1278,7 @@ impl TerminalState {
             *current_index -= 1;
             self.current_input = self.command_history[*current_index].clone();
             self.cursor_position = self.current_input.len();
+            self.invalidate_selection(self.input_line_id());
         }
     }
     
@@ -214,18 +1293,29 @@ impl TerminalState {
                 self.command_index = None;
             }
             self.cursor_position = self.current_input.len();
+            self.invalidate_selection(self.input_line_id());
         }
     }
 
     // Input handling
     pub fn add_input(&mut self, input: &str) {
+        // Keep the cursor solid while typing
+        self.blink_epoch = Instant::now();
         // Reset command_index when typing after history navigation
         self.command_index = None;
         self.current_input.push_str(input);
         self.cursor_position += input.len();
+        self.invalidate_selection(self.input_line_id());
         println!("CURRENT INPUT: {:#?}", self.current_input);
     }
 
+    // Echoes a paste on the input line and returns what to send, bracketed if the application asked
+    pub fn paste(&mut self, text: &str) -> Vec<u8> {
+        let text = clipboard::sanitize_paste(text);
+        self.add_input(&text);
+        clipboard::encode_paste(&text, self.modes.bracketed_paste)
+    }
+
     pub fn handle_backspace(&mut self) {
         
         if self.cursor_position > 0 {
@@ -233,6 +1323,7 @@ impl TerminalState {
             self.current_input.remove(self.cursor_position);
             // Reset command_index to break out of history mode
             self.command_index = None;
+            self.invalidate_selection(self.input_line_id());
             println!("AFTER: {:#?}", self.current_input);
         }
     }
@@ -250,78 +1341,593 @@ impl TerminalState {
         println!("COMMITTED INPUT: {:#?}", self.current_input);
         self.cursor_position = 0;
         self.command_index = None; // Reset history navigation
-        self.clear_selection();
     }
 
     // Output and viewport handling
     pub fn clear(&mut self) {
+        self.evicted_lines += self.history.len() as u64;
+        self.images.clear();
         self.history.clear();
+        self.cursor_line = self.input_line_id();
         self.viewport.offset = 0;
         self.clear_selection();
     }
 
+    // ED 2 and FF: starts an empty screen below the current output, which stays in the scrollback
+    pub fn erase_screen(&mut self) {
+        self.screen_top = self.input_line_id();
+        self.viewport.offset = 0;
+    }
+
     pub fn add_output(&mut self, output: &str) {
-        if output.contains("\x1b[H\x1b[2J") || output.contains("\x0C") {
-            self.clear();
+        let mut parser = std::mem::take(&mut self.parser);
+        for byte in output.bytes() {
+            match (self.apc, byte) {
+                (ApcScanner::Ground, 0x1b) => self.apc = ApcScanner::Escape,
+                (ApcScanner::Ground, _) => parser.advance(self, byte),
+                (ApcScanner::Escape, b'_') => {
+                    self.apc = ApcScanner::Body;
+                    self.apc_body.clear();
+                }
+                (ApcScanner::Escape, 0x1b) => parser.advance(self, 0x1b),
+                (ApcScanner::Escape, _) => {
+                    self.apc = ApcScanner::Ground;
+                    parser.advance(self, 0x1b);
+                    parser.advance(self, byte);
+                }
+                (ApcScanner::Body, 0x1b) => self.apc = ApcScanner::BodyEscape,
+                (ApcScanner::Body, 0x07) | (ApcScanner::BodyEscape, b'\\') => {
+                    self.apc = ApcScanner::Ground;
+                    let body = std::mem::take(&mut self.apc_body);
+                    self.kitty_graphics(&body);
+                }
+                (ApcScanner::Body, _) => {
+                    // Direct transmissions come in chunks, so a single command never needs to be huge
+                    if self.apc_body.len() < MAX_APC_BYTES {
+                        self.apc_body.push(byte);
+                    }
+                }
+                // An escape that doesn't terminate the string cancels it and starts a new sequence
+                (ApcScanner::BodyEscape, _) => {
+                    self.apc = ApcScanner::Ground;
+                    self.apc_body.clear();
+                    parser.advance(self, 0x1b);
+                    parser.advance(self, byte);
+                }
+            }
+        }
+        self.parser = parser;
+
+        if self.viewport.offset == 0 {
+            self.scroll_to_bottom();
+        }
+    }
+
+    fn push_line(&mut self) {
+        // The new line takes over the input line's id, and the input line moves down
+        let input_id = self.input_line_id();
+        let line = std::mem::take(&mut self.pending_line);
+        // The visible screen lives in the history too, so it doesn't count against the scrollback limit
+        let max_lines = self.settings.scrollback_lines + self.viewport.visible_lines;
+        let evicted = self.history.push(&line, max_lines, self.settings.scrollback_max_bytes);
+        if evicted > 0 {
+            self.evicted_lines += evicted as u64;
+            self.images.evict_before(self.evicted_lines);
+        }
+        self.invalidate_selection(input_id);
+        self.cursor_line = self.input_line_id();
+        self.cursor_column = 0;
+        self.pending_wrap = false;
+    }
+
+    // Search
+    pub fn start_search(&mut self) {
+        if self.search.is_none() {
+            self.search = Some(Search::new(SearchMode::Plain));
+        }
+    }
+
+    pub fn close_search(&mut self) {
+        self.search = None;
+    }
+
+    pub fn search_active(&self) -> bool {
+        self.search.is_some()
+    }
+
+    pub fn search_input(&mut self, text: &str) {
+        if let Some(search) = &mut self.search {
+            search.query.push_str(text);
+            self.run_search();
+        }
+    }
+
+    pub fn search_backspace(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            self.run_search();
+        }
+    }
+
+    pub fn cycle_search_mode(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.mode = search.mode.next();
+            self.run_search();
+        }
+    }
+
+    fn run_search(&mut self) {
+        let snapshot = self.history.snapshot();
+        let (first_line, max_matches) = (self.evicted_lines, self.settings.search_max_matches);
+        if let Some(search) = &mut self.search {
+            search.current = None;
+            search.start(snapshot, first_line, max_matches);
+        }
+    }
+
+    // Picks up finished searches, jumping to the match closest to the bottom of the view
+    pub fn poll_search(&mut self) {
+        self.collect_search(false);
+    }
+
+    fn collect_search(&mut self, wait: bool) {
+        let (_, end) = self.get_visible_range();
+        let last_visible = self.evicted_lines + end as u64;
+        let Some(search) = &mut self.search else {
             return;
+        };
+        let updated = if wait { search.wait() } else { search.poll() };
+        if !updated {
+            return;
+        }
+        // In vi mode the search runs from the vi cursor in the direction it was started with
+        search.current = match &self.vi {
+            Some(vi) => search::next_match(&search.matches, vi.cursor, vi.search_backwards),
+            None => {
+                let closest = search.matches.iter().rposition(|m| m.start.0 < last_visible);
+                closest.or((!search.matches.is_empty()).then_some(0))
+            }
+        };
+        if let Some(m) = search.current.map(|index| search.matches[index]) {
+            self.scroll_to_line(m.start.0);
+        }
+    }
+
+    pub fn search_step(&mut self, backwards: bool) {
+        if let Some(m) = self.search.as_mut().and_then(|search| search.step(backwards)) {
+            self.scroll_to_line(m.start.0);
         }
+    }
 
-        for line in output.lines() {
-            if self.history.len() >= MAX_HISTORY_LINES {
-                self.history.pop_front();
+    pub fn search_status(&self) -> Option<String> {
+        self.search.as_ref().map(Search::status)
+    }
+
+    // (line, start column, end column, is current) for the matched cells on screen
+    pub fn visible_search_matches(&self) -> Vec<(usize, usize, usize, bool)> {
+        let Some(search) = &self.search else {
+            return Vec::new();
+        };
+        let (start, end) = self.get_visible_range();
+        let (first, last) = (self.evicted_lines + start as u64, self.evicted_lines + end as u64);
+        let lengths: Vec<usize> = self.history.range(start, end).iter().map(Line::len).collect();
+
+        let from = search.matches.partition_point(|m| m.end.0 < first);
+        let mut spans = Vec::new();
+        for (index, m) in search.matches.iter().enumerate().skip(from) {
+            if m.start.0 >= last {
+                break;
+            }
+            let current = search.current == Some(index);
+            for id in m.start.0.max(first)..=m.end.0.min(last - 1) {
+                let line = (id - first) as usize;
+                let from_column = if id == m.start.0 { m.start.1 } else { 0 };
+                let to_column = if id == m.end.0 { m.end.1 } else { lengths[line] };
+                spans.push((line, from_column, to_column, current));
             }
-            self.history.push_back(line.to_string());
         }
+        spans
+    }
 
-        if self.viewport.offset == 0 {
+    // Hints
+    // Labels the matches of the hint rules on screen; nothing happens when there are none
+    pub fn start_hints(&mut self) {
+        let rules = hints::compile(&self.settings.hint_rules);
+        let (start, _) = self.get_visible_range();
+        let rows: Vec<Line> = (0..self.visible_row_count()).map(|row| self.buffer_row(start + row)).collect();
+
+        // Matches are looked for in logical lines, so a wrapped URL still gets a single hint
+        let mut found = Vec::new();
+        let mut first = 0;
+        for (row, line) in rows.iter().enumerate() {
+            if line.wrapped && row + 1 < rows.len() {
+                continue;
+            }
+            let group = &rows[first..=row];
+            let lengths: Vec<usize> = group.iter().map(Line::len).collect();
+            let chars: Vec<char> = group.iter().flat_map(|line| line.cells.iter().map(|cell| cell.c)).collect();
+            for (from, to, action) in hints::find(&rules, &chars) {
+                let (group_row, column) = locate(&lengths, from, false);
+                let text = chars[from..to].iter().collect();
+                found.push(((self.line_id(start + first + group_row), column), text, action));
+            }
+            first = row + 1;
+        }
+        if found.is_empty() {
+            return;
+        }
+
+        let labels = hints::labels(found.len(), &self.settings.hint_alphabet);
+        let hints = found
+            .into_iter()
+            .zip(labels)
+            .map(|((start, text, action), label)| Hint { label, start, text, action })
+            .collect();
+        self.hints = Some(Hints { hints, typed: String::new() });
+    }
+
+    pub fn hints_active(&self) -> bool {
+        self.hints.is_some()
+    }
+
+    pub fn cancel_hints(&mut self) {
+        self.hints = None;
+    }
+
+    // Typed label characters; returns the hint once its label is complete, and leaves hints mode on a miss
+    pub fn hint_input(&mut self, text: &str) -> Option<Hint> {
+        for c in text.chars() {
+            match self.hints.as_mut()?.input(c) {
+                HintInput::Pending => {}
+                HintInput::Chosen(hint) => {
+                    self.hints = None;
+                    return Some(hint);
+                }
+                HintInput::Cancelled => {
+                    self.hints = None;
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn hint_backspace(&mut self) {
+        if let Some(hints) = &mut self.hints {
+            hints.typed.pop();
+        }
+    }
+
+    // (row, column, untyped rest of the label) for the hints still matching what was typed
+    pub fn visible_hints(&self) -> Vec<(usize, usize, String)> {
+        let Some(hints) = &self.hints else {
+            return Vec::new();
+        };
+        hints
+            .hints
+            .iter()
+            .filter_map(|hint| {
+                let rest = hint.label.strip_prefix(hints.typed.as_str())?;
+                let row = self.visible_row(self.line_index(hint.start.0))?;
+                Some((row, hint.start.1, rest.to_string()))
+            })
+            .collect()
+    }
+
+    // Vi copy mode
+    pub fn toggle_vi_mode(&mut self) {
+        if self.vi.take().is_some() {
+            self.close_search();
             self.scroll_to_bottom();
+            return;
+        }
+        // Start on the application cursor, or on the bottom row when scrolled back
+        let (_, end) = self.get_visible_range();
+        let cursor = match self.cursor_cell() {
+            Some((_, column)) => (self.cursor_line, column),
+            None => (self.evicted_lines + end.saturating_sub(1) as u64, 0),
+        };
+        self.vi = Some(ViMode::new(cursor));
+    }
+
+    pub fn vi_mode_active(&self) -> bool {
+        self.vi.is_some()
+    }
+
+    fn vi_row_chars(&self, index: usize) -> Vec<char> {
+        self.buffer_row(index).cells.iter().map(|cell| cell.c).collect()
+    }
+
+    // Printable vi commands; y is handled by the caller since it needs the clipboard
+    pub fn vi_input(&mut self, text: &str) {
+        for c in text.chars() {
+            let Some(vi) = &mut self.vi else {
+                return;
+            };
+            if std::mem::take(&mut vi.pending_g) {
+                if c == 'g' {
+                    self.vi_motion(ViMotion::Top);
+                }
+                continue;
+            }
+            match c {
+                'g' => vi.pending_g = true,
+                'v' => self.vi_select(SelectionKind::Simple),
+                'V' => self.vi_select(SelectionKind::Lines),
+                '/' | '?' => {
+                    vi.search_backwards = c == '?';
+                    self.start_search();
+                }
+                'n' => self.vi_search_next(false),
+                'N' => self.vi_search_next(true),
+                c => {
+                    if let Some(motion) = ViMotion::from_char(c) {
+                        self.vi_motion(motion);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn vi_motion(&mut self, motion: ViMotion) {
+        let Some(vi) = &self.vi else {
+            return;
+        };
+        let (line, column) = (self.line_index(vi.cursor.0), vi.cursor.1);
+        let last = self.history.len();
+        let (start, end) = self.get_visible_range();
+        let bottom = if self.viewport.offset == 0 { last } else { end.saturating_sub(1) };
+
+        let (line, column) = match motion {
+            ViMotion::Left => (line, column.saturating_sub(1)),
+            ViMotion::Right => (line, column + 1),
+            ViMotion::Up => (line.saturating_sub(1), column),
+            ViMotion::Down => (min(line + 1, last), column),
+            ViMotion::LineStart => (line, 0),
+            ViMotion::LineEnd => (line, usize::MAX),
+            ViMotion::Top => (0, 0),
+            ViMotion::Bottom => (last, 0),
+            ViMotion::ScreenTop => (start, column),
+            ViMotion::ScreenMiddle => ((start + bottom) / 2, column),
+            ViMotion::ScreenBottom => (bottom, column),
+            ViMotion::WordForward | ViMotion::WordBackward | ViMotion::WordEnd => {
+                vi_mode::word_motion(motion, &|index| self.vi_row_chars(index), last, (line, column))
+            }
+        };
+        let column = min(column, self.buffer_row(line).len().saturating_sub(1));
+        self.vi_move_to(line, column);
+    }
+
+    fn vi_move_to(&mut self, line: usize, column: usize) {
+        let id = self.evicted_lines + line as u64;
+        if let Some(vi) = &mut self.vi {
+            vi.cursor = (id, column);
+        }
+        self.reveal_row(line);
+        self.sync_vi_selection();
+    }
+
+    pub fn vi_select(&mut self, kind: SelectionKind) {
+        if let Some(vi) = &mut self.vi {
+            vi.toggle_selection(kind);
+        }
+        self.sync_vi_selection();
+    }
+
+    // Escape drops the selection first and leaves vi mode once there is none
+    pub fn vi_escape(&mut self) {
+        match &mut self.vi {
+            Some(vi) if vi.anchor.is_some() => {
+                vi.anchor = None;
+                self.sync_vi_selection();
+            }
+            Some(_) => self.toggle_vi_mode(),
+            None => {}
+        }
+    }
+
+    // Enter in the search bar moves the vi cursor to the current match and keeps the matches for n and N
+    pub fn vi_search_confirm(&mut self) {
+        // Enter can arrive before the worker replies, so finish the query first
+        self.collect_search(true);
+        let Some(search) = self.search.take() else {
+            return;
+        };
+        let current = search.current.map(|index| search.matches[index]);
+        if let Some(vi) = &mut self.vi {
+            vi.matches = search.matches;
+        }
+        if let Some(m) = current {
+            self.vi_move_to(self.line_index(m.start.0), m.start.1);
+        }
+    }
+
+    fn vi_search_next(&mut self, reverse: bool) {
+        let Some(vi) = &self.vi else {
+            return;
+        };
+        let backwards = vi.search_backwards != reverse;
+        if let Some(index) = search::next_match(&vi.matches, vi.cursor, backwards) {
+            let (line, column) = vi.matches[index].start;
+            self.vi_move_to(self.line_index(line), column);
+        }
+    }
+
+    // Copies the selection, or the cursor line when nothing is selected, and clears the selection
+    pub fn vi_yank(&mut self) -> String {
+        let Some(vi) = &mut self.vi else {
+            return String::new();
+        };
+        let (anchor, kind) = vi.anchor.take().unwrap_or((vi.cursor, SelectionKind::Lines));
+        let cursor = vi.cursor;
+        let text = self.get_text_from_selection(&self.vi_selection(anchor, cursor, kind));
+        self.sync_vi_selection();
+        text
+    }
+
+    // The vi cursor within the visible content, when it is on screen
+    pub fn vi_cursor_cell(&self) -> Option<(usize, usize)> {
+        let vi = self.vi.as_ref()?;
+        let row = self.visible_row(self.line_index(vi.cursor.0))?;
+        Some((row, vi.cursor.1))
+    }
+
+    // Row on screen of a history index, where the input line follows the history
+    fn visible_row(&self, index: usize) -> Option<usize> {
+        let (start, end) = self.get_visible_range();
+        let shown = index >= start && (index < end || (index == self.history.len() && self.viewport.offset == 0));
+        shown.then(|| index - start)
+    }
+
+    // Scrolls the least needed to show a row, scrolling to the bottom for the input line
+    fn reveal_row(&mut self, index: usize) {
+        let (start, end) = self.get_visible_range();
+        let rows = self.viewport.visible_lines.saturating_sub(1);
+        let screen_start = self.screen_start();
+        if index >= self.history.len() {
+            self.viewport.offset = 0;
+        } else if index < start {
+            self.viewport.offset = screen_start - index;
+        } else if index >= end {
+            self.viewport.offset = screen_start.saturating_sub(index + 1 - rows);
         }
     }
 
+    fn sync_vi_selection(&mut self) {
+        let Some(vi) = &self.vi else {
+            return;
+        };
+        self.selection = vi.anchor.map(|(anchor, kind)| self.vi_selection(anchor, vi.cursor, kind));
+    }
+
+    // The cells between the anchor and the vi cursor, both included
+    fn vi_selection(&self, anchor: (u64, usize), cursor: (u64, usize), kind: SelectionKind) -> Selection {
+        let (first, last) = (min(anchor, cursor), max(anchor, cursor));
+        let (from, to) = match kind {
+            SelectionKind::Block => ((first.0, min(anchor.1, cursor.1)), (last.0, max(anchor.1, cursor.1) + 1)),
+            SelectionKind::Lines => ((first.0, 0), (last.0, self.buffer_row(self.line_index(last.0)).len())),
+            SelectionKind::Simple | SelectionKind::Semantic => (first, (last.0, last.1 + 1)),
+        };
+        let range = (Position { line: from.0, column: from.1 }, Position { line: to.0, column: to.1 });
+        Selection::new(range, kind)
+    }
+
+    // Scrolls just enough to bring an absolute line into view, centering it when it was off screen
+    fn scroll_to_line(&mut self, id: u64) {
+        let Some(index) = id.checked_sub(self.evicted_lines).map(|index| index as usize) else {
+            return;
+        };
+        let (start, end) = self.get_visible_range();
+        if (start..end).contains(&index) {
+            return;
+        }
+        let rows = self.viewport.visible_lines.saturating_sub(1);
+        let screen_start = self.screen_start();
+        let offset = (screen_start + rows).saturating_sub(index + 1 + rows / 2);
+        // Lines above the screen's first row only show once scrolled back
+        let offset = if index < screen_start { offset.max(1) } else { offset };
+        self.viewport.offset = min(offset, screen_start);
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.history.len()
+    }
+
+    // Bytes held by the scrollback, including the compressed pages
+    pub fn scrollback_memory_usage(&self) -> usize {
+        self.history.memory_usage()
+    }
+
     pub fn scroll_up(&mut self, lines: usize) {
-        let max_scroll = self.history.len()
-            .saturating_sub(self.viewport.visible_lines.saturating_sub(1));
+        let max_scroll = self.screen_start();
         self.viewport.offset = min(self.viewport.offset + lines, max_scroll);
-        self.clear_selection();
     }
 
     pub fn scroll_down(&mut self, lines: usize) {
         self.viewport.offset = self.viewport.offset.saturating_sub(lines);
-        self.clear_selection();
     }
 
     pub fn scroll_to_bottom(&mut self) {
         self.viewport.offset = 0;
-        self.clear_selection();
     }
 
     // Getters and utility functions
+    // Scrolling back moves the window up from the screen's first row, one line per step
     fn get_visible_range(&self) -> (usize, usize) {
         let total_lines = self.history.len();
+        let screen_start = self.screen_start();
+        if self.viewport.offset == 0 {
+            return (screen_start, total_lines);
+        }
         let visible_lines = self.viewport.visible_lines.saturating_sub(1);
-        let start = total_lines.saturating_sub(visible_lines + self.viewport.offset);
-        let end = total_lines.saturating_sub(self.viewport.offset);
+        let start = screen_start.saturating_sub(self.viewport.offset);
+        let end = min(start + visible_lines, total_lines);
         (start, end)
     }
 
-    pub fn get_visible_content(&self) -> Vec<(String, Color)> {
-        let mut result = Vec::new();
-        let (start, end) = self.get_visible_range();
+    // History index of the first screen row while following the output
+    fn screen_start(&self) -> usize {
+        let rows = self.viewport.visible_lines.saturating_sub(1);
+        max(self.history.len().saturating_sub(rows), self.line_index(self.screen_top))
+    }
 
-        for line in self.history.range(start..end) {
-            result.push((line.clone(), self.settings.colors.text));
+    // Cursor cell within the visible content, only shown while following the input line. It sits on the
+    // application cursor, or at the local echo position while nothing has been written on the current line
+    pub fn cursor_cell(&self) -> Option<(usize, usize)> {
+        if self.viewport.offset != 0 {
+            return None;
+        }
+        let (row, column) = self.application_cursor();
+        let (start, _) = self.get_visible_range();
+        if self.line_index(self.cursor_line) < start {
+            return None;
+        }
+        if self.cursor_column > 0 || self.pending_line.len() > 0 || self.cursor_line != self.input_line_id() {
+            return Some((row - 1, column - 1));
         }
+        let input_column = self.current_input.get(..self.cursor_position).map_or(0, |input| input.chars().count());
+        Some((row - 1, self.settings.prompt.chars().count() + input_column))
+    }
+
+    pub fn get_visible_runs(&self) -> Vec<Vec<StyledRun>> {
+        let (start, end) = self.get_visible_range();
+        let mut result: Vec<Vec<StyledRun>> = self.history
+            .range(start, end)
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| self.line_runs(idx, line))
+            .collect();
 
         if self.viewport.offset == 0 {
-            result.push((
-                format!("{}{}", self.settings.prompt, self.current_input),
-                self.settings.colors.input,
-            ));
+            result.push(vec![StyledRun {
+                text: format!("{}{}", self.settings.prompt, self.current_input),
+                column: 0,
+                fg: self.settings.colors.input,
+                bg: None,
+                underline: false,
+                strikethrough: false,
+            }]);
         }
 
         result
     }
 
+    // Shape to draw at the cursor cell this frame, None while hidden or in the off phase of a blink
+    pub fn cursor_shape(&self) -> Option<CursorShape> {
+        if !self.modes.cursor_visible || self.cursor_cell().is_none() {
+            return None;
+        }
+        let style = self.modes.cursor_style;
+        // Only blink while focused, the unfocused hollow cursor stays put
+        if style.blinking && self.settings.cursor_blink && self.focused {
+            let interval = self.settings.cursor_blink_interval.as_millis().max(1);
+            if (self.blink_epoch.elapsed().as_millis() / interval) % 2 == 1 {
+                return None;
+            }
+        }
+        Some(style.shape)
+    }
+
     pub fn get_viewport(&self) -> &TerminalViewport {
         &self.viewport
     }
@@ -329,5 +1935,353 @@ impl TerminalState {
     pub fn get_settings(&self) -> &TerminalSettings {
         &self.settings
     }
+
+    pub fn get_settings_mut(&mut self) -> &mut TerminalSettings {
+        &mut self.settings
+    }
 }
 
+impl Perform for TerminalState {
+    fn print(&mut self, c: char) {
+        // A character written in the last column wraps before the next one is printed
+        if self.pending_wrap {
+            self.pending_line.wrapped = true;
+            self.push_line();
+        }
+
+        // Stored lines can't be rewritten, so printing after DECRC to an earlier line lands on the current one
+        self.cursor_line = self.input_line_id();
+        let c = self.charsets.map(c);
+        let cell = Cell { c, style: self.style, hyperlink: self.active_hyperlink };
+        self.pending_line.put(self.cursor_column, cell);
+
+        if self.cursor_column < self.last_column() {
+            self.cursor_column += 1;
+        } else {
+            self.pending_wrap = self.modes.autowrap;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.push_line(),
+            b'\r' => {
+                self.cursor_column = 0;
+                self.pending_wrap = false;
+            }
+            b'\t' => {
+                // Tabs leave blanks behind so the skipped columns still render
+                let stop = self.next_tab_stop();
+                while self.pending_line.len() < stop {
+                    self.pending_line.put(self.pending_line.len(), Cell::default());
+                }
+                self.cursor_column = stop;
+                self.pending_wrap = false;
+            }
+            0x08 => {
+                self.cursor_column = self.cursor_column.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            0x07 => self.ring_bell(),
+            0x0C => self.erase_screen(),
+            0x0E => self.charsets.shift(1),
+            0x0F => self.charsets.shift(0),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore {
+            return;
+        }
+
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            ([], b'c') => self.full_reset(),
+            ([], b'H') => {
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor_column) {
+                    *stop = true;
+                }
+            }
+            ([slot @ (b'(' | b')' | b'*' | b'+')], designator) => {
+                if let Some(charset) = Charset::from_designator(designator) {
+                    self.charsets.designate((slot - b'(') as usize, charset);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        match (intermediates, action) {
+            ([], 'J') => match params.iter().next() {
+                Some([2]) => self.erase_screen(),
+                Some([3]) => self.clear(),
+                _ => {}
+            },
+            ([], 'm') => self.style.apply_sgr(params),
+            ([], 'C') | ([], 'D') | ([], 'G') => {
+                let count = params.iter().next().map_or(1, |param| param[0].max(1)) as usize;
+                self.cursor_column = match action {
+                    'C' => min(self.cursor_column + count, self.last_column()),
+                    'D' => self.cursor_column.saturating_sub(count),
+                    _ => min(count - 1, self.last_column()),
+                };
+                self.pending_wrap = false;
+            }
+            ([], 'K') => {
+                let column = self.cursor_column;
+                match params.iter().next().map_or(0, |param| param[0]) {
+                    0 => self.pending_line.erase(column, usize::MAX),
+                    1 => self.pending_line.erase(0, column + 1),
+                    2 => self.pending_line.erase(0, usize::MAX),
+                    _ => {}
+                }
+            }
+            ([], 'g') => match params.iter().next().map_or(0, |param| param[0]) {
+                0 => {
+                    if let Some(stop) = self.tab_stops.get_mut(self.cursor_column) {
+                        *stop = false;
+                    }
+                }
+                3 => self.tab_stops.iter_mut().for_each(|stop| *stop = false),
+                _ => {}
+            },
+            ([], 's') => self.save_cursor(),
+            ([], 'u') => self.restore_cursor(),
+            ([b'!'], 'p') => self.soft_reset(),
+            ([b'?'], 'h') | ([b'?'], 'l') => {
+                for param in params.iter() {
+                    self.set_private_mode(param[0], action == 'h');
+                }
+            }
+            ([], 'n') | ([b'?'], 'n') => {
+                let request = params.iter().next().map_or(0, |param| param[0]);
+                self.device_status_report(request, !intermediates.is_empty());
+            }
+            ([], 'c') if matches!(params.iter().next(), None | Some([0])) => {
+                self.reply(reports::PRIMARY_ATTRIBUTES);
+            }
+            ([b'>'], 'c') if matches!(params.iter().next(), None | Some([0])) => {
+                self.reply(&reports::secondary_attributes());
+            }
+            ([b'='], 'c') if matches!(params.iter().next(), None | Some([0])) => {
+                self.reply(reports::TERTIARY_ATTRIBUTES);
+            }
+            ([b'>'], 'q') if matches!(params.iter().next(), None | Some([0])) => {
+                self.reply(&reports::xtversion());
+            }
+            ([b'?'], 'S') => {
+                let mut params = params.iter().map(|param| param[0]);
+                let item = params.next().unwrap_or(0);
+                let action = params.next().unwrap_or(0);
+                let reply = self.graphics_attribute_reply(item, action);
+                self.reply(&reply);
+            }
+            ([b'$'], 'p') | ([b'?', b'$'], 'p') => {
+                let private = intermediates[0] == b'?';
+                let mode = params.iter().next().map_or(0, |param| param[0]);
+                let status = if private { self.private_mode_status(mode) } else { self.ansi_mode_status(mode) };
+                self.reply(&reports::mode_report(mode, private, status));
+            }
+            ([b' '], 'q') => {
+                let param = params.iter().next().map_or(0, |param| param[0]);
+                if let Some(style) = CursorStyle::from_decscusr(param) {
+                    self.modes.cursor_style = style;
+                }
+            }
+            ([], 't') => {
+                let mut params = params.iter().map(|param| param[0]);
+                let operation = params.next().unwrap_or(0);
+                let target = TitleTarget::from_param(params.next().unwrap_or(0));
+                match (operation, target) {
+                    (22, Some(target)) => self.titles.push(target),
+                    (23, Some(target)) => self.titles.pop(target),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        self.dcs = match (intermediates, action) {
+            _ if ignore => None,
+            ([], 'q') => {
+                let params: Vec<u16> = params.iter().map(|param| param[0]).collect();
+                let max_pixels = self.settings.image_max_bytes / 4;
+                Some(DcsRequest::Sixel(Box::new(SixelDecoder::new(&params, max_pixels))))
+            }
+            ([b'+'], 'q') => Some(DcsRequest::GetTermcap(Vec::new())),
+            ([b'$'], 'q') => Some(DcsRequest::StatusString(Vec::new())),
+            _ => None,
+        };
+    }
+
+    fn put(&mut self, byte: u8) {
+        match &mut self.dcs {
+            Some(DcsRequest::GetTermcap(data) | DcsRequest::StatusString(data)) if data.len() < MAX_DCS_REQUEST_BYTES => {
+                data.push(byte);
+            }
+            Some(DcsRequest::Sixel(decoder)) => decoder.put(byte),
+            _ => {}
+        }
+    }
+
+    fn unhook(&mut self) {
+        match self.dcs.take() {
+            Some(DcsRequest::GetTermcap(data)) => self.reply(&termcap::xtgettcap_reply(&data)),
+            Some(DcsRequest::StatusString(data)) => {
+                let reply = self.status_string_reply(&data);
+                self.reply(&reply);
+            }
+            Some(DcsRequest::Sixel(decoder)) => {
+                if let Some(image) = decoder.finish() {
+                    let size = (image.width, image.height);
+                    self.place_image(image, size, true);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        let Some(command) = params.first().and_then(|p| std::str::from_utf8(p).ok()) else {
+            return;
+        };
+        // Replies use the same string terminator as the request
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+
+        match command {
+            "0" => self.set_title(TitleTarget::Both, &params[1..]),
+            "1" => self.set_title(TitleTarget::IconName, &params[1..]),
+            "2" => self.set_title(TitleTarget::WindowTitle, &params[1..]),
+            "8" if params.len() >= 3 => {
+                // OSC 8 ; params ; URI, where the URI itself may contain semicolons
+                let link_params = String::from_utf8_lossy(params[1]);
+                let uri = params[2..]
+                    .iter()
+                    .map(|p| String::from_utf8_lossy(p))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                self.active_hyperlink = if uri.is_empty() {
+                    None
+                } else {
+                    Some(self.hyperlinks.intern(&link_params, &uri))
+                };
+            }
+            "52" if params.len() >= 3 => {
+                let requests = clipboard::parse_osc52(params[1], params[2], self.settings.clipboard_max_bytes, terminator);
+                self.clipboard_requests.extend(requests);
+            }
+            "4" => self.set_palette_colors(&params[1..], terminator),
+            "10" | "11" | "12" | "17" | "19" => {
+                let first = command.parse().unwrap_or(10);
+                self.set_dynamic_colors(first, &params[1..], terminator);
+            }
+            "1337" => self.file_transfer(&params[1..]),
+            "104" => self.reset_palette_colors(&params[1..]),
+            "110" | "111" | "112" | "117" | "119" => {
+                let code = command.parse::<u16>().unwrap_or(110) - 100;
+                self.set_dynamic_color(code, None);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Lines beyond the in-memory limits go to disk when configured, and are dropped otherwise
+fn new_scrollback(settings: &TerminalSettings) -> Scrollback {
+    if !settings.scrollback_on_disk {
+        return Scrollback::default();
+    }
+    let directory = &settings.scrollback_directory;
+    Scrollback::with_disk(directory, settings.keep_scrollback_file, settings.shred_scrollback_file)
+        .unwrap_or_else(|e| {
+            eprintln!("Scrollback: disk storage unavailable: {}", e);
+            Scrollback::default()
+        })
+}
+
+// XDG_DOWNLOAD_DIR when the session exports it, ~/Downloads otherwise
+fn default_download_directory() -> PathBuf {
+    if let Some(directory) = std::env::var_os("XDG_DOWNLOAD_DIR") {
+        return PathBuf::from(directory);
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    home.join("Downloads")
+}
+
+// The run of word characters around a column, or just the column when it holds a separator
+fn word_range(chars: &[char], column: usize, separators: &str) -> (usize, usize) {
+    let is_word = |c: &char| !separators.contains(*c);
+    if !chars.get(column).is_some_and(is_word) {
+        return (column, if column < chars.len() { column + 1 } else { column });
+    }
+    let start = chars[..column].iter().rposition(|c| !is_word(c)).map_or(0, |i| i + 1);
+    let end = chars[column..].iter().position(|c| !is_word(c)).map_or(chars.len(), |i| column + i);
+    (start, end)
+}
+
+// Row and column of a character offset into rows of the given lengths; an end offset at a row
+// boundary stays on the earlier row
+fn locate(lengths: &[usize], offset: usize, at_end: bool) -> (usize, usize) {
+    let mut start = 0;
+    for (row, &length) in lengths.iter().enumerate() {
+        if offset < start + length || (at_end && offset == start + length) {
+            return (row, offset - start);
+        }
+        start += length;
+    }
+    let last = lengths.len().saturating_sub(1);
+    (last, offset - (start - lengths.get(last).copied().unwrap_or(0)))
+}
+
+// $VISUAL, then $EDITOR, then vi
+fn default_editor() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string())
+}
+
+fn default_tab_stops(columns: usize) -> Vec<bool> {
+    (0..columns).map(|column| column > 0 && column % TAB_WIDTH == 0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn word_range_stops_at_separators() {
+        let line = chars("ls -la ~/src/main.rs");
+        assert_eq!(word_range(&line, 4, " "), (3, 6));
+        assert_eq!(word_range(&line, 10, " "), (7, 20));
+        assert_eq!(word_range(&line, 10, " /"), (9, 12));
+    }
+
+    #[test]
+    fn word_range_takes_a_lone_separator_or_nothing_past_the_end() {
+        let line = chars("a  b");
+        assert_eq!(word_range(&line, 1, " "), (1, 2));
+        assert_eq!(word_range(&line, 9, " "), (9, 9));
+        assert_eq!(word_range(&chars("wörd"), 2, " "), (0, 4));
+    }
+
+    #[test]
+    fn locate_keeps_end_offsets_on_the_earlier_row() {
+        let lengths = [3, 3, 2];
+        assert_eq!(locate(&lengths, 3, false), (1, 0));
+        assert_eq!(locate(&lengths, 3, true), (0, 3));
+        assert_eq!(locate(&lengths, 8, true), (2, 2));
+    }
+}
@@ -0,0 +1,97 @@
+// Window and icon titles (OSC 0/1/2) with the XTWINOPS title stack (CSI 22 t / CSI 23 t)
+
+pub const DEFAULT_TITLE: &str = "Mikoshi";
+const MAX_TITLE_STACK: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TitleTarget {
+    Both,
+    IconName,
+    WindowTitle,
+}
+
+impl TitleTarget {
+    // Maps the OSC number or the XTWINOPS second parameter to what it affects
+    pub fn from_param(param: u16) -> Option<Self> {
+        match param {
+            0 => Some(TitleTarget::Both),
+            1 => Some(TitleTarget::IconName),
+            2 => Some(TitleTarget::WindowTitle),
+            _ => None,
+        }
+    }
+
+    fn icon(self) -> bool {
+        self != TitleTarget::WindowTitle
+    }
+
+    fn window(self) -> bool {
+        self != TitleTarget::IconName
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TitleState {
+    title: Option<String>,
+    icon_name: Option<String>,
+    stack: Vec<(Option<String>, Option<String>)>,
+}
+
+impl TitleState {
+    pub fn set(&mut self, target: TitleTarget, text: &str, max_length: usize) {
+        let text = sanitize(text, max_length);
+        if target.icon() {
+            self.icon_name = Some(text.clone());
+        }
+        if target.window() {
+            self.title = Some(text);
+        }
+    }
+
+    pub fn push(&mut self, target: TitleTarget) {
+        if self.stack.len() >= MAX_TITLE_STACK {
+            self.stack.remove(0);
+        }
+        let icon_name = if target.icon() { self.icon_name.clone() } else { None };
+        let title = if target.window() { self.title.clone() } else { None };
+        self.stack.push((title, icon_name));
+    }
+
+    pub fn pop(&mut self, target: TitleTarget) {
+        if let Some((title, icon_name)) = self.stack.pop() {
+            if target.icon() && icon_name.is_some() {
+                self.icon_name = icon_name;
+            }
+            if target.window() && title.is_some() {
+                self.title = title;
+            }
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(DEFAULT_TITLE)
+    }
+
+    pub fn icon_name(&self) -> &str {
+        self.icon_name.as_deref().unwrap_or(DEFAULT_TITLE)
+    }
+
+    // Expands {title}, {icon} and {command} in the configured title template
+    pub fn format(&self, template: &str, command: &str, max_length: usize) -> String {
+        let formatted = template
+            .replace("{title}", self.title())
+            .replace("{icon}", self.icon_name())
+            .replace("{command}", command);
+        sanitize(&formatted, max_length)
+    }
+}
+
+// Strips control characters and caps the length so hostile output can't mangle the title bar
+fn sanitize(text: &str, max_length: usize) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .take(max_length)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
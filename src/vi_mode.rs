@@ -0,0 +1,171 @@
+// Vi-style copy mode: a keyboard cursor over the scrollback that moves independently of the application
+
+use super::search::SearchMatch;
+use super::terminal_state::SelectionKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViMotion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    Top,
+    Bottom,
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+}
+
+// Positions are (absolute line id, column), so they stay put while output scrolls the history
+pub struct ViMode {
+    pub cursor: (u64, usize),
+    pub anchor: Option<((u64, usize), SelectionKind)>,
+    // Set after `g`, waiting for the second `g`
+    pub pending_g: bool,
+    pub search_backwards: bool,
+    // Matches of the last confirmed search, for `n` and `N`
+    pub matches: Vec<SearchMatch>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+impl ViMotion {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'h' => Some(ViMotion::Left),
+            'l' => Some(ViMotion::Right),
+            'k' => Some(ViMotion::Up),
+            'j' => Some(ViMotion::Down),
+            'w' => Some(ViMotion::WordForward),
+            'b' => Some(ViMotion::WordBackward),
+            'e' => Some(ViMotion::WordEnd),
+            '0' => Some(ViMotion::LineStart),
+            '$' => Some(ViMotion::LineEnd),
+            'G' => Some(ViMotion::Bottom),
+            'H' => Some(ViMotion::ScreenTop),
+            'M' => Some(ViMotion::ScreenMiddle),
+            'L' => Some(ViMotion::ScreenBottom),
+            _ => None,
+        }
+    }
+}
+
+impl ViMode {
+    pub fn new(cursor: (u64, usize)) -> Self {
+        Self {
+            cursor,
+            anchor: None,
+            pending_g: false,
+            search_backwards: false,
+            matches: Vec::new(),
+        }
+    }
+
+    // v, V and Ctrl-v start a selection of that kind, switch an existing one, or end it when repeated
+    pub fn toggle_selection(&mut self, kind: SelectionKind) {
+        self.anchor = match self.anchor {
+            Some((_, current)) if current == kind => None,
+            Some((anchor, _)) => Some((anchor, kind)),
+            None => Some((self.cursor, kind)),
+        };
+    }
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// Steps through the rows one cell at a time, with the end of each row acting as whitespace
+struct Walker<'a> {
+    row: &'a dyn Fn(usize) -> Vec<char>,
+    last_line: usize,
+    line: usize,
+    column: usize,
+    chars: Vec<char>,
+}
+
+impl Walker<'_> {
+    fn class(&self) -> CharClass {
+        self.chars.get(self.column).map_or(CharClass::Space, |&c| char_class(c))
+    }
+
+    fn forward(&mut self) -> bool {
+        if self.column < self.chars.len() {
+            self.column += 1;
+        } else if self.line < self.last_line {
+            self.line += 1;
+            self.chars = (self.row)(self.line);
+            self.column = 0;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn backward(&mut self) -> bool {
+        if self.column > 0 {
+            self.column -= 1;
+        } else if self.line > 0 {
+            self.line -= 1;
+            self.chars = (self.row)(self.line);
+            self.column = self.chars.len();
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+// w, b and e: the start of the next word, the start of this or the previous word, and the end of a word
+pub fn word_motion(motion: ViMotion, row: &dyn Fn(usize) -> Vec<char>, last_line: usize, (line, column): (usize, usize)) -> (usize, usize) {
+    let mut walker = Walker { row, last_line, line, column, chars: row(line) };
+    match motion {
+        ViMotion::WordForward => {
+            let class = walker.class();
+            if class != CharClass::Space {
+                while walker.forward() && walker.class() == class {}
+            }
+            while walker.class() == CharClass::Space && walker.forward() {}
+        }
+        ViMotion::WordEnd => {
+            walker.forward();
+            while walker.class() == CharClass::Space && walker.forward() {}
+            let class = walker.class();
+            while walker.forward() {
+                if walker.class() != class {
+                    walker.backward();
+                    break;
+                }
+            }
+        }
+        ViMotion::WordBackward => {
+            walker.backward();
+            while walker.class() == CharClass::Space && walker.backward() {}
+            let class = walker.class();
+            while walker.backward() {
+                if walker.class() != class {
+                    walker.forward();
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+    (walker.line, walker.column)
+}